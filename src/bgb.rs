@@ -1,12 +1,343 @@
+use std::collections::VecDeque;
 use std::io::{self, Read, Write};
-use std::net::TcpStream;
+use std::net::{TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::PathBuf;
 use std::sync::mpsc;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
-// Note: Instant used only for verbose logging (last_exchange_time), not for BGB timestamps.
+// Note: Instant times local things (logging, keepalives, idle backoff), never BGB timestamps.
 
-use crate::protocol::BgbPacket;
+use crate::protocol::{BgbPacket, StatusFlags};
+use crate::speed::Speed;
+use crate::verbosity::Verbosity;
+
+/// Where BGB's link cable server is listening.
+#[derive(Debug, Clone)]
+pub enum BgbEndpoint {
+    Tcp { host: String, port: u16 },
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl std::fmt::Display for BgbEndpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BgbEndpoint::Tcp { host, port } => write!(f, "{}:{}", host, port),
+            #[cfg(unix)]
+            BgbEndpoint::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Byte stream to BGB. `bgb_thread` only needs these operations, so it
+/// works the same over TCP and Unix sockets.
+pub trait LinkStream: Read + Write + Send {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl LinkStream for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl LinkStream for UnixStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+/// Default answer to BGB-initiated transfers while we have nothing to send.
+const DEFAULT_IDLE_REPLY: u8 = 0x00;
+
+/// How long `BgbClient::shutdown` waits for the background thread.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long `BgbEndpoint::probe` waits for each TCP connect.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+impl BgbEndpoint {
+    fn open(&self) -> Result<Box<dyn LinkStream>, String> {
+        match self {
+            BgbEndpoint::Tcp { host, port } => {
+                let addr = format!("{}:{}", host, port);
+                let stream = TcpStream::connect(&addr)
+                    .map_err(|e| format!("TCP connect to {}: {}", addr, e))?;
+                stream.set_nodelay(true).ok();
+                Ok(Box::new(stream))
+            }
+            #[cfg(unix)]
+            BgbEndpoint::Unix(path) => {
+                let stream = UnixStream::connect(path)
+                    .map_err(|e| format!("Unix socket connect to {}: {}", path.display(), e))?;
+                Ok(Box::new(stream))
+            }
+        }
+    }
+
+    /// Open and immediately close a connection, to check something is
+    /// listening without starting a link session. TCP connects give up
+    /// after `PROBE_TIMEOUT`, since the GUI waits on this.
+    pub fn probe(&self) -> Result<(), String> {
+        match self {
+            BgbEndpoint::Tcp { host, port } => {
+                let addr = format!("{}:{}", host, port);
+                let resolved = (host.as_str(), *port).to_socket_addrs()
+                    .map_err(|e| format!("resolving {}: {}", addr, e))?;
+                let mut last_err = format!("{} resolved to no addresses", addr);
+                for sock_addr in resolved {
+                    match TcpStream::connect_timeout(&sock_addr, PROBE_TIMEOUT) {
+                        Ok(_) => return Ok(()),
+                        Err(e) => last_err = format!("TCP connect to {}: {}", addr, e),
+                    }
+                }
+                Err(last_err)
+            }
+            #[cfg(unix)]
+            BgbEndpoint::Unix(_) => self.open().map(drop),
+        }
+    }
+}
+
+/// Version numbers BGB reports in its handshake reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BgbVersion {
+    /// Link protocol version (1 for all current BGB builds)
+    pub protocol: u8,
+    pub major: u8,
+    pub minor: u8,
+}
+
+impl std::fmt::Display for BgbVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "protocol v{}, version {}.{}", self.protocol, self.major, self.minor)
+    }
+}
+
+/// Upper bounds, in ms, of the exchange latency histogram's buckets. One
+/// more bucket takes everything slower.
+pub const LATENCY_BUCKETS_MS: [u64; 4] = [10, 25, 50, 100];
+
+/// Counters shared between the BGB thread and whoever owns the client.
+#[derive(Debug, Default)]
+pub struct LinkStats {
+    /// Number of sync1 transfers we have initiated
+    pub exchanges: AtomicU64,
+    /// Round trip of the most recent completed exchange
+    pub last_latency_ms: AtomicU64,
+    /// Completed exchanges per `LATENCY_BUCKETS_MS` bucket
+    pub latency_histogram: [AtomicU64; LATENCY_BUCKETS_MS.len() + 1],
+    /// Link bytes not yet collected by `take_byte_trace`, oldest first
+    byte_trace: Mutex<VecDeque<(Direction, u8)>>,
+    /// Cleared when the background thread exits
+    pub connected: AtomicBool,
+    /// extra1 of BGB's last status packet. BGB documents the field as
+    /// unused; `GameConfig::follow_bgb_speed` reads it as emulation speed in
+    /// quarters of normal speed (4 = 1x), with 0 meaning none reported.
+    pub reported_speed: AtomicU8,
+}
+
+impl LinkStats {
+    /// Record a completed exchange's round trip.
+    pub fn record_latency(&self, ms: u64) {
+        self.last_latency_ms.store(ms, Ordering::Relaxed);
+        let bucket = LATENCY_BUCKETS_MS.iter().position(|&bound| ms < bound).unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.latency_histogram[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Note a byte crossing the link. Only the newest `BYTE_TRACE_LEN` wait
+    /// to be collected, which bounds how fast the trace reaches the GUI.
+    pub fn trace_byte(&self, direction: Direction, byte: u8) {
+        let mut trace = self.byte_trace.lock().unwrap();
+        if trace.len() == BYTE_TRACE_LEN {
+            trace.pop_front();
+        }
+        trace.push_back((direction, byte));
+    }
+
+    /// Bytes traced since the last call, oldest first.
+    pub fn take_byte_trace(&self) -> Vec<(Direction, u8)> {
+        self.byte_trace.lock().unwrap().drain(..).collect()
+    }
+
+    /// Exchange counts per latency bucket, fastest first.
+    pub fn latency_histogram(&self) -> [u64; LATENCY_BUCKETS_MS.len() + 1] {
+        std::array::from_fn(|i| self.latency_histogram[i].load(Ordering::Relaxed))
+    }
+}
+
+/// How many traced bytes `LinkStats` holds for collection.
+const BYTE_TRACE_LEN: usize = 32;
+
+/// How many packets `PacketHistory` keeps.
+const PACKET_HISTORY_LEN: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// One packet in the history, numbered in the order it crossed the socket.
+#[derive(Debug, Clone, Copy)]
+pub struct PacketRecord {
+    pub index: u64,
+    pub direction: Direction,
+    pub packet: BgbPacket,
+}
+
+impl std::fmt::Display for PacketRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let arrow = match self.direction {
+            Direction::Sent => "->",
+            Direction::Received => "<-",
+        };
+        write!(f, "#{} {} {}", self.index, arrow, self.packet)
+    }
+}
+
+/// The last `PACKET_HISTORY_LEN` packets sent or received, kept whether or
+/// not verbose logging is on so the tail of a failed session can be dumped.
+#[derive(Debug, Default)]
+pub struct PacketHistory {
+    inner: Mutex<(u64, VecDeque<PacketRecord>)>,
+}
+
+impl PacketHistory {
+    fn record(&self, direction: Direction, packet: BgbPacket) {
+        let mut inner = self.inner.lock().unwrap();
+        let (next, packets) = &mut *inner;
+        if packets.len() == PACKET_HISTORY_LEN {
+            packets.pop_front();
+        }
+        packets.push_back(PacketRecord { index: *next, direction, packet });
+        *next += 1;
+    }
+
+    /// Copy of the buffered packets, oldest first.
+    pub fn snapshot(&self) -> Vec<PacketRecord> {
+        self.inner.lock().unwrap().1.iter().copied().collect()
+    }
+}
+
+/// How often the background thread polls BGB while idle. The wait starts at
+/// `min` and doubles up to `max` while nothing is sent or received; any
+/// traffic resets it. A larger `max` saves CPU (useful on battery) but
+/// delays noticing packets BGB sends on its own, such as status updates.
+/// Exchanges are not slowed: a byte to send wakes the thread at once, and
+/// waiting for sync2 always polls at `min`.
+#[derive(Debug, Clone, Copy)]
+pub struct PollInterval {
+    pub min: Duration,
+    pub max: Duration,
+    /// Send a status packet after this long without sending anything, for
+    /// BGB setups that drop a quiet link. Never sent mid-exchange. None disables.
+    pub keepalive: Option<Duration>,
+}
+
+impl Default for PollInterval {
+    fn default() -> Self {
+        Self { min: Duration::from_millis(1), max: Duration::from_millis(16), keepalive: None }
+    }
+}
+
+/// How long `BgbClient` waits on BGB. A short handshake timeout fails fast
+/// when BGB isn't ready; a long exchange timeout tolerates stepping through
+/// a paused emulator.
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    /// For the version reply during `connect`
+    pub handshake: Duration,
+    /// For each byte's reply in `exchange_byte`
+    pub exchange: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self { handshake: Duration::from_secs(3), exchange: Duration::from_secs(5) }
+    }
+}
+
+/// Which side drives the serial clock on our sync1 transfers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialClock {
+    /// Internal clock: we are master and BGB's Game Boy is the slave
+    Master,
+    /// External clock: BGB's Game Boy is expected to drive transfers
+    Slave,
+}
+
+impl SerialClock {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "master" => Some(SerialClock::Master),
+            "slave" => Some(SerialClock::Slave),
+            _ => None,
+        }
+    }
+
+    /// SC register value sent in the `extra1` byte of sync1.
+    pub fn sc(self) -> u8 {
+        match self {
+            SerialClock::Master => 0x81,
+            SerialClock::Slave => 0x80,
+        }
+    }
+}
+
+/// Out-of-band requests from the client to its background thread.
+enum Control {
+    /// Forget any in-flight transfer and re-announce our status; the sender
+    /// is signalled once the thread has done so.
+    Reset(mpsc::Sender<()>),
+    /// Stop answering BGB-initiated transfers with 0x00: pass their data to
+    /// the first sender and answer with whatever arrives on the receiver.
+    Relay(mpsc::Sender<u8>, mpsc::Receiver<u8>),
+    /// Use this clock for subsequent sync1 packets
+    SetClock(SerialClock),
+    /// Set or clear the paused flag and send our status right away
+    SetPaused(bool),
+}
+
+/// The slave side of a `BgbClient` in relay mode: bytes BGB's Game Boy
+/// clocks out as master, and where to put the answer for each.
+pub struct SlaveLink {
+    pub incoming: mpsc::Receiver<u8>,
+    pub answer: mpsc::Sender<u8>,
+}
+
+/// Channel ends the background thread owns.
+struct ThreadChannels {
+    send_rx: mpsc::Receiver<u8>,
+    /// Each reply's data and control (SC) bytes
+    recv_tx: mpsc::Sender<(u8, u8)>,
+    control_rx: mpsc::Receiver<Control>,
+}
+
+/// Settings the background thread starts with.
+struct ThreadOptions {
+    verbose: Verbosity,
+    speed: Speed,
+    poll: PollInterval,
+    /// Flags sent in our status packets; `paused` can change at runtime
+    status: StatusFlags,
+    /// Reply to transfers BGB starts while we have nothing to send
+    idle_reply: Arc<AtomicU8>,
+}
 
 /// Thread-safe BGB client. Spawns a background thread that continuously
 /// reads BGB packets and responds to sync/status. Data exchange happens
@@ -14,72 +345,208 @@ use crate::protocol::BgbPacket;
 pub struct BgbClient {
     /// Send a byte to exchange with BGB
     send_tx: mpsc::Sender<u8>,
-    /// Receive the response byte from BGB
-    recv_rx: mpsc::Receiver<u8>,
+    /// Receive the response data and control bytes from BGB
+    recv_rx: mpsc::Receiver<(u8, u8)>,
+    /// Out-of-band requests such as `reset_link`
+    control_tx: mpsc::Sender<Control>,
+    /// Counters updated by the background thread
+    stats: Arc<LinkStats>,
+    /// Recent packets, filled in by the background thread
+    history: Arc<PacketHistory>,
+    /// Byte the background thread answers unsolicited transfers with
+    idle_reply: Arc<AtomicU8>,
+    /// How long to wait for each reply
+    exchange_timeout: Duration,
+    /// What BGB reported during the handshake
+    version: BgbVersion,
     /// Handle to the background thread
-    _thread: std::thread::JoinHandle<()>,
+    thread: std::thread::JoinHandle<()>,
 }
 
 impl BgbClient {
-    pub fn connect(host: &str, port: u16, log_tx: Option<mpsc::Sender<String>>, verbose: Arc<AtomicBool>) -> Result<Self, String> {
-        let addr = format!("{}:{}", host, port);
-        let mut stream = TcpStream::connect(&addr)
-            .map_err(|e| format!("TCP connect to {}: {}", addr, e))?;
-        stream.set_nodelay(true).ok();
+    pub fn connect(
+        endpoint: &BgbEndpoint,
+        log_tx: Option<mpsc::Sender<String>>,
+        verbose: Verbosity,
+        speed: Speed,
+        poll: PollInterval,
+        status: StatusFlags,
+        timeouts: Timeouts,
+    ) -> Result<Self, String> {
+        let mut stream = endpoint.open()?;
 
-        // Perform handshake on this thread before spawning
-        handshake(&mut stream)?;
+        // Perform handshake on this thread before spawning. The stream is
+        // still blocking here, so bound the reads in case BGB accepts the
+        // connection but never replies.
+        stream.set_read_timeout(Some(timeouts.handshake)).ok();
+        let version = handshake(&mut *stream, status, timeouts.handshake)?;
+        stream.set_read_timeout(None).ok();
+        if let Some(ref tx) = log_tx {
+            let _ = tx.send(format!("Connected to BGB {}", version));
+        }
+
+        let idle_reply = Arc::new(AtomicU8::new(DEFAULT_IDLE_REPLY));
+        if let Some(ref tx) = log_tx {
+            let _ = tx.send(format!("Answering BGB-initiated transfers with 0x{:02X}", DEFAULT_IDLE_REPLY));
+        }
 
         let (send_tx, send_rx) = mpsc::channel::<u8>();
-        let (recv_tx, recv_rx) = mpsc::channel::<u8>();
+        let (recv_tx, recv_rx) = mpsc::channel::<(u8, u8)>();
+        let (control_tx, control_rx) = mpsc::channel();
+        let channels = ThreadChannels { send_rx, recv_tx, control_rx };
 
+        let stats = Arc::new(LinkStats::default());
+        stats.connected.store(true, Ordering::Relaxed);
+        let thread_stats = stats.clone();
+        let history = Arc::new(PacketHistory::default());
+        let thread_history = history.clone();
+        let thread_idle_reply = idle_reply.clone();
         let thread = std::thread::spawn(move || {
-            bgb_thread(stream, send_rx, recv_tx, log_tx, verbose);
+            let options = ThreadOptions { verbose, speed, poll, status, idle_reply: thread_idle_reply };
+            bgb_thread(stream, channels, log_tx, options, &thread_stats, &thread_history);
+            thread_stats.connected.store(false, Ordering::Relaxed);
         });
 
         Ok(Self {
             send_tx,
             recv_rx,
-            _thread: thread,
+            control_tx,
+            stats,
+            history,
+            idle_reply,
+            exchange_timeout: timeouts.exchange,
+            version,
+            thread,
         })
     }
 
-    /// Exchange one byte with BGB. Sends the byte and waits for the response.
-    /// Times out after 5 seconds.
+    /// Tear down the connection and wait for the background thread.
+    /// Dropping our end of the send channel tells the thread to close the
+    /// BGB socket; returns false if it has not exited within
+    /// `SHUTDOWN_TIMEOUT` (it is then left to finish on its own).
+    pub fn shutdown(self) -> bool {
+        let Self { send_tx, thread, .. } = self;
+        drop(send_tx);
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+        while !thread.is_finished() {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        thread.join().is_ok()
+    }
+
+    /// Version numbers BGB reported during the handshake.
+    pub fn version(&self) -> BgbVersion {
+        self.version
+    }
+
+    /// Shared link counters. Stays valid after the client is dropped.
+    pub fn stats(&self) -> Arc<LinkStats> {
+        self.stats.clone()
+    }
+
+    /// Byte to answer transfers BGB starts while we have nothing to send.
+    /// Takes effect from the next such transfer.
+    pub fn set_idle_reply(&self, byte: u8) {
+        self.idle_reply.store(byte, Ordering::Relaxed);
+    }
+
+    /// The last packets exchanged with BGB, oldest first.
+    pub fn dump_recent_packets(&self) -> Vec<PacketRecord> {
+        self.history.snapshot()
+    }
+
+    /// Exchange one byte with BGB. Sends the byte and waits for the response,
+    /// up to the exchange timeout.
     pub fn exchange_byte(&self, send: u8) -> Result<u8, String> {
+        self.exchange_byte_full(send).map(|(data, _)| data)
+    }
+
+    /// Like `exchange_byte`, but also returns the control byte BGB sent with
+    /// the reply (`extra1`: SC for a sync2, or BGB's own SC when both sides
+    /// started the transfer at once).
+    pub fn exchange_byte_full(&self, send: u8) -> Result<(u8, u8), String> {
         self.send_tx.send(send).map_err(|_| "BGB thread died".to_string())?;
-        self.recv_rx.recv_timeout(Duration::from_secs(5))
+        self.recv_rx.recv_timeout(self.exchange_timeout)
             .map_err(|_| "BGB exchange timeout".to_string())
     }
+
+    /// Switch to relay mode: transfers BGB's Game Boy starts as master are
+    /// handed to the caller instead of being answered with 0x00. Our own
+    /// exchanges keep working as before.
+    pub fn relay_incoming(&self) -> Result<SlaveLink, String> {
+        let (incoming_tx, incoming) = mpsc::channel();
+        let (answer, answer_rx) = mpsc::channel();
+        self.control_tx.send(Control::Relay(incoming_tx, answer_rx))
+            .map_err(|_| "BGB thread died".to_string())?;
+        Ok(SlaveLink { incoming, answer })
+    }
+
+    /// Change the SC byte sent with subsequent transfers. Replies are still
+    /// matched by timestamp, so an exchange already in flight is unaffected.
+    pub fn set_clock(&self, clock: SerialClock) -> Result<(), String> {
+        self.control_tx.send(Control::SetClock(clock)).map_err(|_| "BGB thread died".to_string())
+    }
+
+    /// Ask BGB to pause or resume emulation by announcing our status with
+    /// the paused flag set or cleared.
+    pub fn set_paused(&self, paused: bool) -> Result<(), String> {
+        self.control_tx.send(Control::SetPaused(paused)).map_err(|_| "BGB thread died".to_string())
+    }
+
+    /// Recover from a desync without reconnecting: the background thread
+    /// abandons any transfer still waiting for sync2, drops bytes not yet
+    /// sent and re-sends its status, then any responses already queued for
+    /// us are discarded. Returns the number of discarded responses.
+    pub fn reset_link(&self) -> Result<usize, String> {
+        let (done_tx, done_rx) = mpsc::channel();
+        self.control_tx.send(Control::Reset(done_tx)).map_err(|_| "BGB thread died".to_string())?;
+        done_rx.recv_timeout(Duration::from_secs(1))
+            .map_err(|_| "BGB reset timeout".to_string())?;
+        Ok(self.recv_rx.try_iter().count())
+    }
 }
 
-fn handshake(stream: &mut TcpStream) -> Result<(), String> {
+fn handshake(stream: &mut dyn LinkStream, status: StatusFlags, timeout: Duration) -> Result<BgbVersion, String> {
     // Send version: protocol 1, max 4
     send_packet(stream, &BgbPacket::new(1, 1, 4, 0, 0))?;
 
     // Read version response
-    let resp = read_packet(stream).map_err(|e| format!("BGB handshake read: {}", e))?;
+    let resp = read_packet(stream).map_err(|e| {
+        if is_timeout(&e) {
+            format!("BGB handshake timeout (no reply within {:.1}s)", timeout.as_secs_f32())
+        } else {
+            format!("BGB handshake read: {}", e)
+        }
+    })?;
     if resp.command != 1 {
         return Err(format!("Expected version (cmd=1), got cmd={}", resp.command));
     }
 
-    // Send initial status (running) — timestamp 0, BGB will tell us its clock
-    send_packet(stream, &BgbPacket::new(108, 1, 0, 0, 0))?;
+    let version = BgbVersion { protocol: resp.data, major: resp.extra1, minor: resp.extra2 };
 
-    Ok(())
+    // Send initial status — timestamp 0, BGB will tell us its clock
+    send_packet(stream, &BgbPacket::new(108, status.to_byte(), 0, 0, 0))?;
+
+    Ok(version)
 }
 
 /// Background thread: continuously reads BGB packets, responds to sync/status,
 /// and handles data exchange requests from the main thread.
 fn bgb_thread(
-    mut stream: TcpStream,
-    send_rx: mpsc::Receiver<u8>,
-    recv_tx: mpsc::Sender<u8>,
+    mut stream: Box<dyn LinkStream>,
+    channels: ThreadChannels,
     log_tx: Option<mpsc::Sender<String>>,
-    verbose: Arc<AtomicBool>,
+    options: ThreadOptions,
+    stats: &LinkStats,
+    history: &PacketHistory,
 ) {
     // Non-blocking mode — we manually poll with short sleeps
     stream.set_nonblocking(true).ok();
+    let ThreadChannels { send_rx, recv_tx, control_rx } = channels;
+    let ThreadOptions { verbose, speed, poll, mut status, idle_reply } = options;
 
     let log = |msg: String| {
         if let Some(ref tx) = log_tx {
@@ -87,8 +554,34 @@ fn bgb_thread(
         }
     };
 
-    let vlog = |msg: String| {
-        if verbose.load(Ordering::Relaxed) {
+    // A lost connection dumps the packet history so the log keeps the
+    // tail of the session even without verbose logging
+    let lost = |msg: String| {
+        log(msg);
+        let packets = history.snapshot();
+        log(format!("Last {} BGB packets:", packets.len()));
+        for record in packets {
+            log(record.to_string());
+        }
+    };
+
+    // When we last sent anything, for the keepalive
+    let last_sent = std::cell::Cell::new(Instant::now());
+    let send = |stream: &mut Box<dyn LinkStream>, pkt: &BgbPacket| {
+        history.record(Direction::Sent, *pkt);
+        last_sent.set(Instant::now());
+        send_packet(stream, pkt)
+    };
+
+    // Our side went away: tell BGB we're leaving rather than just dropping
+    // the socket
+    let hang_up = |stream: &mut Box<dyn LinkStream>, timestamp: u32| {
+        log("Bridge dropped, closing BGB connection".into());
+        let _ = send(stream, &BgbPacket::new(109, 0, 0, 0, timestamp));
+    };
+
+    let vlog = |category: u8, msg: String| {
+        if verbose.enabled(category) {
             if let Some(ref tx) = log_tx {
                 let _ = tx.send(msg);
             }
@@ -97,6 +590,7 @@ fn bgb_thread(
 
     let mut waiting_for_response = false;
     let mut pending_byte: u8 = 0; // The byte we sent in our last cmd=104
+    let mut pending_timestamp: u32 = 0; // The timestamp we sent with it
     let mut read_buf = [0u8; 64];
     let mut read_pos: usize = 0;
     let mut exchange_count: u64 = 0;
@@ -106,30 +600,103 @@ fn bgb_thread(
     // that were too far behind BGB's internal clock, causing multi-second stalls.
     // Instead, we echo BGB's last received timestamp + small offset.
     let mut last_received_timestamp: u32 = 0;
+    // Current idle wait, between poll.min and poll.max
+    let mut idle_sleep = poll.min;
+    // Byte received while idling, sent on the next pass
+    let mut woken_by: Option<u8> = None;
+    // Relay mode channels, and the timestamp of a BGB transfer awaiting its answer
+    let mut relay: Option<(mpsc::Sender<u8>, mpsc::Receiver<u8>)> = None;
+    let mut relay_pending: Option<u32> = None;
+    // Bytes dropped so far while looking for the next packet boundary
+    let mut resync_skipped: usize = 0;
+    // Whether BGB's last status disagreed with our requested pause state
+    let mut pause_mismatch = false;
+    // SC byte for our sync1 packets
+    let mut clock = SerialClock::Master;
 
     loop {
+        // Control requests are handled even mid-transfer; that is when a
+        // reset is needed
+        match control_rx.try_recv() {
+            Ok(Control::Reset(done_tx)) => {
+                if waiting_for_response {
+                    log(format!("Link reset: abandoning sync1 #{} (sent 0x{:02X})", exchange_count, pending_byte));
+                }
+                waiting_for_response = false;
+                // Bytes from exchanges that gave up waiting would otherwise go
+                // out after the reset, and their replies land on later exchanges
+                let queued = usize::from(woken_by.take().is_some()) + send_rx.try_iter().count();
+                if queued > 0 {
+                    log(format!("Link reset: dropping {} queued byte(s)", queued));
+                }
+                if send(&mut stream, &BgbPacket::new(108, status.to_byte(), 0, 0, last_received_timestamp)).is_err() {
+                    lost("BGB send failed, disconnecting".into());
+                    return;
+                }
+                let _ = done_tx.send(());
+            }
+            Ok(Control::Relay(incoming_tx, answer_rx)) => relay = Some((incoming_tx, answer_rx)),
+            Ok(Control::SetPaused(paused)) => {
+                if paused != status.paused {
+                    log(format!("Asking BGB to {}", if paused { "pause" } else { "resume" }));
+                    status.paused = paused;
+                }
+                if send(&mut stream, &BgbPacket::new(108, status.to_byte(), 0, 0, last_received_timestamp)).is_err() {
+                    lost("BGB send failed, disconnecting".into());
+                    return;
+                }
+            }
+            Ok(Control::SetClock(new)) => {
+                if new != clock {
+                    log(format!("Link clock {:?} -> {:?} (SC=0x{:02X})", clock, new, new.sc()));
+                    clock = new;
+                }
+            }
+            Err(_) => {}
+        }
+
+        // Answer a relayed BGB-initiated transfer once its reply is ready
+        if let (Some(ts), Some((_, answer_rx))) = (relay_pending, &relay) {
+            if let Ok(answer) = answer_rx.try_recv() {
+                let _ = send(&mut stream, &BgbPacket::new(105, answer, 0x80, 0, ts));
+                vlog(Verbosity::SEND, format!("[RELAY] answered BGB with 0x{:02X}", answer));
+                relay_pending = None;
+            }
+        }
+
         // Check if there's a byte to send (non-blocking)
         if !waiting_for_response {
-            match send_rx.try_recv() {
+            let next = match woken_by.take() {
+                Some(byte) => Ok(byte),
+                None => send_rx.try_recv(),
+            };
+            match next {
                 Ok(byte) => {
-                    // Use BGB's last timestamp + small fixed offset.
-                    // This tells BGB "I'm at the same point in time as you"
-                    let ts = last_received_timestamp.wrapping_add(8);
-                    // SC=0x81: internal clock (master). Tetris requires the web
-                    // client to drive the clock; BGB's Game Boy is the slave.
-                    if send_packet(&mut stream, &BgbPacket::new(104, byte, 0x81, 0, ts)).is_err() {
-                        log("BGB send failed, disconnecting".into());
+                    // Use BGB's last timestamp + small offset, scaled by the
+                    // emulation speed. This tells BGB "I'm at the same point in time as you"
+                    let ts = last_received_timestamp.wrapping_add(speed.scale_ticks(8));
+                    // SC=0x81 by default: internal clock (master). Tetris requires
+                    // the web client to drive the clock; BGB's Game Boy is the
+                    // slave. With an external clock BGB may answer with its own
+                    // sync1, which the simultaneous-exchange path below accepts.
+                    let sync1 = BgbPacket::new(104, byte, clock.sc(), 0, ts);
+                    if send(&mut stream, &sync1).is_err() {
+                        lost("BGB send failed, disconnecting".into());
                         return;
                     }
+                    stats.trace_byte(Direction::Sent, byte);
                     pending_byte = byte;
+                    pending_timestamp = ts;
                     waiting_for_response = true;
                     exchange_count += 1;
+                    stats.exchanges.fetch_add(1, Ordering::Relaxed);
                     last_exchange_time = Instant::now();
                     last_wait_log_secs = 0;
-                    vlog(format!("[SEND] sync1 #{}: data=0x{:02X} sc=0x81 ts={}", exchange_count, byte, ts));
+                    idle_sleep = poll.min;
+                    vlog(Verbosity::SEND, format!("[SEND] #{}: {}", exchange_count, sync1));
                 }
                 Err(mpsc::TryRecvError::Disconnected) => {
-                    log("Bridge dropped, closing BGB connection".into());
+                    hang_up(&mut stream, last_received_timestamp);
                     return;
                 }
                 Err(mpsc::TryRecvError::Empty) => {}
@@ -139,11 +706,13 @@ fn bgb_thread(
         // Read available bytes into packet buffer (non-blocking, no desync risk)
         match stream.read(&mut read_buf[read_pos..]) {
             Ok(0) => {
-                log("BGB connection closed".into());
+                lost("BGB connection closed".into());
                 return;
             }
             Ok(n) => {
                 read_pos += n;
+                debug_assert!(read_pos <= read_buf.len());
+                idle_sleep = poll.min;
             }
             Err(ref e) if is_timeout(e) => {
                 // No data available right now
@@ -152,36 +721,83 @@ fn bgb_thread(
                     let waited_secs = waited.as_secs();
                     if waited_secs >= 2 && waited_secs > last_wait_log_secs {
                         last_wait_log_secs = waited_secs;
-                        vlog(format!("[WAIT] sync2 for #{} (sent 0x{:02X}): waiting {}s...",
+                        vlog(Verbosity::RECV, format!("[WAIT] sync2 for #{} (sent 0x{:02X}): waiting {}s...",
                             exchange_count, pending_byte, waited_secs));
                     }
-                    // Small sleep to avoid busy-spinning while waiting
-                    std::thread::sleep(Duration::from_millis(1));
-                }
-                if read_pos == 0 && !waiting_for_response {
-                    std::thread::sleep(Duration::from_millis(1));
+                    // Small sleep to avoid busy-spinning while waiting; never
+                    // backed off, this is the exchange's latency
+                    std::thread::sleep(poll.min);
+                } else if relay_pending.is_some() {
+                    // The other Game Boy's answer is on its way
+                    std::thread::sleep(poll.min);
+                } else if read_pos == 0 {
+                    // Idle: keep a quiet link alive, re-asserting our status
+                    // (including the pause state we asked for)
+                    if poll.keepalive.is_some_and(|interval| last_sent.get().elapsed() >= interval) {
+                        let keepalive = BgbPacket::new(108, status.to_byte(), 0, 0, last_received_timestamp);
+                        if send(&mut stream, &keepalive).is_err() {
+                            lost("BGB send failed, disconnecting".into());
+                            return;
+                        }
+                        vlog(Verbosity::SEND, format!("[SEND] keepalive: {}", keepalive));
+                    }
+                    // Wait for the next byte to send, backing off while
+                    // nothing happens. A new byte wakes us immediately.
+                    match send_rx.recv_timeout(idle_sleep) {
+                        Ok(byte) => woken_by = Some(byte),
+                        Err(mpsc::RecvTimeoutError::Timeout) => {
+                            idle_sleep = (idle_sleep * 2).min(poll.max);
+                        }
+                        Err(mpsc::RecvTimeoutError::Disconnected) => {
+                            hang_up(&mut stream, last_received_timestamp);
+                            return;
+                        }
+                    }
                 }
             }
             Err(e) => {
-                log(format!("BGB connection lost: {}", e));
+                lost(format!("BGB connection lost: {}", e));
                 return;
             }
         }
 
         // Process complete packets
         while read_pos >= 8 {
-            let pkt = BgbPacket::from_bytes([
+            let Some(pkt) = BgbPacket::try_from_bytes([
                 read_buf[0], read_buf[1], read_buf[2], read_buf[3],
                 read_buf[4], read_buf[5], read_buf[6], read_buf[7],
-            ]);
+            ]) else {
+                // Framing lost: slide one byte at a time until a known
+                // command byte lines up again
+                read_buf.copy_within(1..read_pos, 0);
+                read_pos -= 1;
+                resync_skipped += 1;
+                continue;
+            };
+            history.record(Direction::Received, pkt);
+            if resync_skipped > 0 {
+                log(format!("Resynced BGB stream after skipping {} byte(s)", resync_skipped));
+                resync_skipped = 0;
+            }
 
-            // Shift remaining bytes to front
+            // Shift remaining bytes to front. Bytes past read_pos are stale
+            // and never read.
             let remaining = read_pos - 8;
             if remaining > 0 {
-                read_buf.copy_within(8.., 0);
+                read_buf.copy_within(8..read_pos, 0);
             }
             read_pos = remaining;
 
+            // A sync2 stamped before our pending sync1 answers an earlier
+            // transfer (delayed or duplicated); matching it would hand the
+            // caller the wrong byte.
+            if pkt.command == 105 && waiting_for_response
+                && is_stale_timestamp(pkt.timestamp, pending_timestamp)
+            {
+                log(format!("Discarding stale {} (pending ts=0x{:08X})", pkt, pending_timestamp));
+                continue;
+            }
+
             // Track BGB's clock so our outgoing timestamps stay in sync.
             if pkt.timestamp != 0 {
                 last_received_timestamp = pkt.timestamp;
@@ -193,66 +809,456 @@ fn bgb_thread(
                         // Simultaneous exchange: both sides sent sync1.
                         // Respond with our pending byte and treat BGB's data as our response.
                         let elapsed_ms = last_exchange_time.elapsed().as_millis();
-                        let _ = send_packet(&mut stream, &BgbPacket::new(105, pending_byte, 0x80, 0, pkt.timestamp));
+                        stats.record_latency(elapsed_ms as u64);
+                        let _ = send(&mut stream, &BgbPacket::new(105, pending_byte, 0x80, 0, pkt.timestamp));
                         waiting_for_response = false;
-                        vlog(format!("[RECV] sync1 #{} (SIMUL): bgb_data=0x{:02X} sc=0x{:02X} -> reply 0x{:02X} ({}ms)",
-                            exchange_count, pkt.data, pkt.extra1, pending_byte, elapsed_ms));
-                        if recv_tx.send(pkt.data).is_err() {
+                        vlog(Verbosity::RECV, format!("[RECV] #{} (SIMUL): {} -> reply 0x{:02X} ({}ms)",
+                            exchange_count, pkt, pending_byte, elapsed_ms));
+                        stats.trace_byte(Direction::Received, pkt.data);
+                        if recv_tx.send((pkt.data, pkt.extra1)).is_err() {
+                            hang_up(&mut stream, last_received_timestamp);
+                            return;
+                        }
+                    } else if let Some((incoming_tx, _)) = &relay {
+                        // Relay mode: the answer comes from the other Game Boy
+                        vlog(Verbosity::RECV, format!("[RECV] (relayed): {}", pkt));
+                        relay_pending = Some(pkt.timestamp);
+                        if incoming_tx.send(pkt.data).is_err() {
+                            hang_up(&mut stream, last_received_timestamp);
                             return;
                         }
                     } else {
                         // BGB initiated a transfer while we have nothing to send
-                        let _ = send_packet(&mut stream, &BgbPacket::new(105, 0, 0x80, 0, pkt.timestamp));
-                        vlog(format!("[RECV] sync1 (unsolicited): bgb_data=0x{:02X} sc=0x{:02X} -> reply 0x00",
-                            pkt.data, pkt.extra1));
+                        let reply = idle_reply.load(Ordering::Relaxed);
+                        let _ = send(&mut stream, &BgbPacket::new(105, reply, 0x80, 0, pkt.timestamp));
+                        stats.trace_byte(Direction::Received, pkt.data);
+                        stats.trace_byte(Direction::Sent, reply);
+                        vlog(Verbosity::RECV, format!("[RECV] (unsolicited): {} -> reply 0x{:02X}", pkt, reply));
                     }
                 }
                 105 => {
                     if waiting_for_response {
                         let elapsed_ms = last_exchange_time.elapsed().as_millis();
+                        stats.record_latency(elapsed_ms as u64);
                         waiting_for_response = false;
-                        vlog(format!("[RECV] sync2 #{}: data=0x{:02X} sc=0x{:02X} ({}ms)",
-                            exchange_count, pkt.data, pkt.extra1, elapsed_ms));
-                        if recv_tx.send(pkt.data).is_err() {
+                        vlog(Verbosity::RECV, format!("[RECV] #{}: {} ({}ms)", exchange_count, pkt, elapsed_ms));
+                        stats.trace_byte(Direction::Received, pkt.data);
+                        if recv_tx.send((pkt.data, pkt.extra1)).is_err() {
+                            hang_up(&mut stream, last_received_timestamp);
                             return;
                         }
                     } else {
-                        vlog(format!("[RECV] sync2 (stale): data=0x{:02X} sc=0x{:02X} — ignoring",
-                            pkt.data, pkt.extra1));
+                        vlog(Verbosity::RECV, format!("[RECV] (stale): {} — ignoring", pkt));
                     }
                 }
                 106 => {
-                    let _ = send_packet(&mut stream, &BgbPacket::new(106, pkt.data, pkt.extra1, pkt.extra2, pkt.timestamp));
-                    vlog(format!("[RECV] sync3: data=0x{:02X}", pkt.data));
+                    // sync3 with data 0 asks for an ack, which is sync3 with
+                    // data 1. Acks are never answered, or the two sides
+                    // would bounce them back and forth forever.
+                    if pkt.data == 0 {
+                        let _ = send(&mut stream, &BgbPacket::new(106, 1, 0, 0, pkt.timestamp));
+                    }
+                    vlog(Verbosity::RECV, format!("[RECV] {}", pkt));
                 }
                 108 => {
-                    let _ = send_packet(&mut stream, &BgbPacket::new(108, 1, 0, 0, pkt.timestamp));
-                    vlog(format!("[RECV] status: data=0x{:02X} extra1=0x{:02X}", pkt.data, pkt.extra1));
+                    // Our reply re-asserts the pause state we asked for, so
+                    // a mismatch only needs logging
+                    let bgb_paused = StatusFlags::from_byte(pkt.data).paused;
+                    if bgb_paused != status.paused && !pause_mismatch {
+                        log(format!("BGB reports {}, re-sending our status",
+                            if bgb_paused { "paused" } else { "running" }));
+                    }
+                    pause_mismatch = bgb_paused != status.paused;
+                    let _ = send(&mut stream, &BgbPacket::new(108, status.to_byte(), 0, 0, pkt.timestamp));
+                    // Undocumented, so only kept for the opt-in follow_bgb_speed
+                    stats.reported_speed.store(pkt.extra1, Ordering::Relaxed);
+                    vlog(Verbosity::RECV, format!("[RECV] {}", pkt));
                 }
                 109 => {
-                    log("BGB sent disconnect".into());
+                    lost("BGB sent disconnect".into());
                     return;
                 }
                 _ => {
-                    vlog(format!("[RECV] unknown cmd={}: data=0x{:02X} extra1=0x{:02X} extra2=0x{:02X}",
-                        pkt.command, pkt.data, pkt.extra1, pkt.extra2));
+                    vlog(Verbosity::RECV, format!("[RECV] {}", pkt));
                 }
             }
         }
     }
 }
 
+/// True if `received` is strictly earlier than `pending` on BGB's wrapping
+/// clock. A zero timestamp carries no clock information and is never stale.
+fn is_stale_timestamp(received: u32, pending: u32) -> bool {
+    received != 0 && (received.wrapping_sub(pending) as i32) < 0
+}
+
 /// Check if an IO error is a timeout/would-block (cross-platform).
 fn is_timeout(e: &io::Error) -> bool {
     matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock)
 }
 
-fn send_packet(stream: &mut TcpStream, pkt: &BgbPacket) -> Result<(), String> {
+fn send_packet<S: Write + ?Sized>(stream: &mut S, pkt: &BgbPacket) -> Result<(), String> {
     stream.write_all(&pkt.to_bytes()).map_err(|e| format!("BGB send: {}", e))
 }
 
-fn read_packet(stream: &mut TcpStream) -> Result<BgbPacket, io::Error> {
+fn read_packet<S: Read + ?Sized>(stream: &mut S) -> Result<BgbPacket, io::Error> {
     let mut buf = [0u8; 8];
     stream.read_exact(&mut buf)?;
     Ok(BgbPacket::from_bytes(buf))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_bgb::{FakeBgb, Reply};
+    use std::net::TcpListener;
+
+    #[test]
+    fn probe_finds_a_listener_and_reports_a_closed_port() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = BgbEndpoint::Tcp { host: "127.0.0.1".into(), port: listener.local_addr().unwrap().port() };
+        assert!(endpoint.probe().is_ok());
+        drop(listener);
+        assert!(endpoint.probe().unwrap_err().starts_with("TCP connect to 127.0.0.1:"));
+    }
+
+    /// Run `bgb_thread` against one end of a local TCP pair and return the
+    /// other end, playing BGB, plus the exchange channels.
+    fn spawn_thread() -> (TcpStream, mpsc::Sender<u8>, mpsc::Receiver<(u8, u8)>) {
+        spawn_thread_with(ThreadOptions {
+            verbose: Verbosity::default(),
+            speed: Speed::default(),
+            poll: PollInterval::default(),
+            status: StatusFlags::default(),
+            idle_reply: Arc::new(AtomicU8::new(DEFAULT_IDLE_REPLY)),
+        })
+    }
+
+    fn spawn_thread_with(options: ThreadOptions) -> (TcpStream, mpsc::Sender<u8>, mpsc::Receiver<(u8, u8)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (bgb, _) = listener.accept().unwrap();
+        let (send_tx, send_rx) = mpsc::channel();
+        let (recv_tx, recv_rx) = mpsc::channel();
+        // Dropping the control sender is fine; try_recv just reports Disconnected
+        let (_, control_rx) = mpsc::channel();
+        let channels = ThreadChannels { send_rx, recv_tx, control_rx };
+        std::thread::spawn(move || {
+            let stats = LinkStats::default();
+            bgb_thread(Box::new(client), channels, None, options, &stats, &PacketHistory::default());
+        });
+        (bgb, send_tx, recv_rx)
+    }
+
+    #[test]
+    fn handshake_timeout_is_separate_from_exchange_timeout() {
+        // Accepts the connection but never answers the version packet
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let endpoint = BgbEndpoint::Tcp { host: "127.0.0.1".into(), port };
+        let timeouts = Timeouts { handshake: Duration::from_millis(100), exchange: Duration::from_secs(60) };
+
+        let started = Instant::now();
+        let result = BgbClient::connect(&endpoint, None, Verbosity::default(), Speed::default(),
+            PollInterval::default(), StatusFlags::default(), timeouts);
+        let Err(e) = result else { panic!("handshake should have timed out") };
+        assert!(e.contains("handshake timeout"), "{}", e);
+        assert!(started.elapsed() < Duration::from_secs(2));
+        drop(listener);
+    }
+
+    #[test]
+    fn idle_link_sends_status_keepalives() {
+        let (mut bgb, _send_tx, _recv_rx) = spawn_thread_with(ThreadOptions {
+            verbose: Verbosity::default(),
+            speed: Speed::default(),
+            poll: PollInterval { keepalive: Some(Duration::from_millis(20)), ..Default::default() },
+            status: StatusFlags { running: true, paused: true, supports_reconnect: false },
+            idle_reply: Arc::new(AtomicU8::new(DEFAULT_IDLE_REPLY)),
+        });
+        bgb.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        for _ in 0..2 {
+            let keepalive = read_packet(&mut bgb).unwrap();
+            assert_eq!(keepalive.command, 108);
+            assert_eq!(keepalive.data, 0x03);
+        }
+    }
+
+    #[test]
+    fn backed_off_idle_link_still_sends_at_once() {
+        let (mut bgb, send_tx, _recv_rx) = spawn_thread_with(ThreadOptions {
+            verbose: Verbosity::default(),
+            speed: Speed::default(),
+            poll: PollInterval { max: Duration::from_secs(10), ..Default::default() },
+            status: StatusFlags::default(),
+            idle_reply: Arc::new(AtomicU8::new(DEFAULT_IDLE_REPLY)),
+        });
+        bgb.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        // Long enough for the wait to have doubled well past a second
+        std::thread::sleep(Duration::from_millis(1500));
+        let started = Instant::now();
+        send_tx.send(0x29).unwrap();
+        let sync1 = read_packet(&mut bgb).unwrap();
+        assert_eq!((sync1.command, sync1.data), (104, 0x29));
+        assert!(started.elapsed() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn dropping_the_client_sends_bgb_a_disconnect() {
+        let (mut bgb, send_tx, _recv_rx) = spawn_thread();
+        bgb.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        drop(send_tx);
+        assert_eq!(read_packet(&mut bgb).unwrap().command, 109);
+    }
+
+    #[test]
+    fn packets_split_into_single_bytes_are_each_handled_once() {
+        let (mut bgb, _send_tx, _recv_rx) = spawn_thread();
+        bgb.set_nodelay(true).unwrap();
+        let packets = [
+            BgbPacket::new(108, 0x01, 0, 0, 100),
+            BgbPacket::new(104, 0x29, 0x81, 0, 200),
+            BgbPacket::new(106, 0x00, 0, 0, 300),
+        ];
+        // Simulate TCP fragmentation: every byte arrives on its own
+        for byte in packets.iter().flat_map(|p| p.to_bytes()) {
+            bgb.write_all(&[byte]).unwrap();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        bgb.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let replies: Vec<(u8, u32)> = (0..3)
+            .map(|_| read_packet(&mut bgb).unwrap())
+            .map(|p| (p.command, p.timestamp))
+            .collect();
+        assert_eq!(replies, [(108, 100), (105, 200), (106, 300)]);
+
+        // Nothing was processed twice
+        bgb.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+        assert!(read_packet(&mut bgb).is_err());
+    }
+
+    #[test]
+    fn stale_sync2_is_discarded() {
+        let (mut bgb, send_tx, recv_rx) = spawn_thread();
+        bgb.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        // Advance the bridge's view of BGB's clock, then start an exchange
+        send_packet(&mut bgb, &BgbPacket::new(108, 1, 0, 0, 1000)).unwrap();
+        let status = read_packet(&mut bgb).unwrap();
+        assert_eq!(status.command, 108);
+        send_tx.send(0x29).unwrap();
+        let sync1 = read_packet(&mut bgb).unwrap();
+        assert_eq!(sync1.command, 104);
+        assert_eq!(sync1.data, 0x29);
+        assert!(sync1.timestamp > 1000);
+
+        // A reply stamped before our sync1, followed by the real one
+        send_packet(&mut bgb, &BgbPacket::new(105, 0xAA, 0x80, 0, 500)).unwrap();
+        send_packet(&mut bgb, &BgbPacket::new(105, 0x55, 0x80, 0, sync1.timestamp)).unwrap();
+
+        assert_eq!(recv_rx.recv_timeout(Duration::from_secs(5)), Ok((0x55, 0x80)));
+        assert!(recv_rx.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn sync3_is_acked_once_and_acks_are_not_answered() {
+        let (mut bgb, _send_tx, _recv_rx) = spawn_thread();
+        bgb.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        send_packet(&mut bgb, &BgbPacket::new(106, 0, 0, 0, 1000)).unwrap();
+        let ack = read_packet(&mut bgb).unwrap();
+        assert_eq!((ack.command, ack.data), (106, 1));
+
+        // Our ack echoed back must not start a loop
+        send_packet(&mut bgb, &ack).unwrap();
+        bgb.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+        assert!(read_packet(&mut bgb).is_err());
+    }
+
+    #[test]
+    fn stray_byte_is_skipped_and_alignment_recovers() {
+        let (mut bgb, send_tx, recv_rx) = spawn_thread();
+        bgb.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        send_tx.send(0x29).unwrap();
+        let sync1 = read_packet(&mut bgb).unwrap();
+
+        // One garbage byte ahead of an otherwise valid reply
+        bgb.write_all(&[0xEE]).unwrap();
+        send_packet(&mut bgb, &BgbPacket::new(105, 0x55, 0x80, 0, sync1.timestamp)).unwrap();
+        assert_eq!(recv_rx.recv_timeout(Duration::from_secs(5)), Ok((0x55, 0x80)));
+
+        // Later packets are still parsed on the right boundary
+        send_packet(&mut bgb, &BgbPacket::new(108, 1, 0, 0, 2000)).unwrap();
+        assert_eq!(read_packet(&mut bgb).unwrap().command, 108);
+        send_tx.send(0x30).unwrap();
+        let sync1 = read_packet(&mut bgb).unwrap();
+        assert_eq!(sync1.data, 0x30);
+        send_packet(&mut bgb, &BgbPacket::new(105, 0x66, 0x80, 0, sync1.timestamp)).unwrap();
+        assert_eq!(recv_rx.recv_timeout(Duration::from_secs(5)), Ok((0x66, 0x80)));
+    }
+
+    #[test]
+    fn packet_history_keeps_the_newest_packets() {
+        let history = PacketHistory::default();
+        for i in 0..300u32 {
+            history.record(Direction::Sent, BgbPacket::new(104, 0, 0x81, 0, i));
+        }
+        let packets = history.snapshot();
+        assert_eq!(packets.len(), PACKET_HISTORY_LEN);
+        assert_eq!(packets[0].index, 300 - PACKET_HISTORY_LEN as u64);
+        assert_eq!(packets.last().unwrap().packet.timestamp, 299);
+    }
+
+    #[test]
+    fn unsolicited_sync1_is_answered_with_idle_reply() {
+        let (mut bgb, _send_tx, _recv_rx) = spawn_thread_with(ThreadOptions {
+            verbose: Verbosity::default(),
+            speed: Speed::default(),
+            poll: PollInterval::default(),
+            status: StatusFlags::default(),
+            idle_reply: Arc::new(AtomicU8::new(0xFF)),
+        });
+        bgb.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        send_packet(&mut bgb, &BgbPacket::new(104, 0x12, 0x81, 0, 4000)).unwrap();
+        let reply = read_packet(&mut bgb).unwrap();
+        assert_eq!(reply.command, 105);
+        assert_eq!(reply.data, 0xFF);
+        assert_eq!(reply.timestamp, 4000);
+    }
+
+    #[test]
+    fn latency_histogram_buckets_by_upper_bound() {
+        let stats = LinkStats::default();
+        for ms in [0, 9, 10, 30, 99, 100, 5000] {
+            stats.record_latency(ms);
+        }
+        assert_eq!(stats.latency_histogram(), [2, 1, 1, 1, 2]);
+        assert_eq!(stats.last_latency_ms.load(Ordering::Relaxed), 5000);
+    }
+
+    #[test]
+    fn byte_trace_keeps_the_newest_bytes_until_taken() {
+        let stats = LinkStats::default();
+        for byte in 0..BYTE_TRACE_LEN as u8 + 3 {
+            stats.trace_byte(Direction::Sent, byte);
+        }
+        let trace = stats.take_byte_trace();
+        assert_eq!(trace.len(), BYTE_TRACE_LEN);
+        assert_eq!(trace[0], (Direction::Sent, 3));
+        assert!(stats.take_byte_trace().is_empty());
+    }
+
+    #[test]
+    fn stale_timestamp_handles_wraparound() {
+        assert!(is_stale_timestamp(99, 100));
+        assert!(!is_stale_timestamp(100, 100));
+        assert!(!is_stale_timestamp(5, u32::MAX - 5));
+        assert!(!is_stale_timestamp(0, 100));
+    }
+
+    #[test]
+    fn reset_drops_sends_still_queued() {
+        let bgb = FakeBgb::start(vec![Reply::Silent, Reply::Sync2(0x33)]);
+        let timeouts = Timeouts { exchange: Duration::from_millis(100), ..Timeouts::default() };
+        let client = BgbClient::connect(&bgb.endpoint, None, Verbosity::default(), Speed::default(),
+            PollInterval::default(), StatusFlags::default(), timeouts).unwrap();
+
+        // 0x0A goes unanswered, so 0x0B is still queued behind it when
+        // its exchange gives up
+        assert!(client.exchange_byte(0x0A).is_err());
+        assert!(client.exchange_byte(0x0B).is_err());
+        client.reset_link().unwrap();
+        assert_eq!(client.exchange_byte(0x0C), Ok(0x33));
+
+        let sent: Vec<u8> = bgb.received(104).iter().map(|p| p.data).collect();
+        assert_eq!(sent, vec![0x0A, 0x0C]);
+    }
+
+    #[test]
+    fn exchange_returns_scripted_replies() {
+        let bgb = FakeBgb::start(vec![Reply::Sync2(0x55), Reply::Sync2(0x12), Reply::Sync2(0xAA)]);
+        let client = bgb.connect();
+
+        assert_eq!(client.exchange_byte(0x29), Ok(0x55));
+        assert_eq!(client.exchange_byte(0x60), Ok(0x12));
+        assert_eq!(client.exchange_byte(0x02), Ok(0xAA));
+
+        let sent: Vec<u8> = bgb.received(104).iter().map(|p| p.data).collect();
+        assert_eq!(sent, vec![0x29, 0x60, 0x02]);
+    }
+
+    #[test]
+    fn exchange_byte_full_returns_the_control_byte() {
+        let bgb = FakeBgb::start(vec![Reply::Sync2(0x55), Reply::Simultaneous(0x22)]);
+        let client = bgb.connect();
+
+        assert_eq!(client.exchange_byte_full(0x29), Ok((0x55, 0x80)));
+        assert_eq!(client.exchange_byte_full(0x02), Ok((0x22, 0x81)));
+    }
+
+    #[test]
+    fn set_clock_changes_sc_and_keeps_matching_replies() {
+        let bgb = FakeBgb::start(vec![Reply::Sync2(0x11), Reply::Simultaneous(0x22), Reply::Sync2(0x33)]);
+        let client = bgb.connect();
+
+        assert_eq!(client.exchange_byte(0x01), Ok(0x11));
+        client.set_clock(SerialClock::Slave).unwrap();
+        assert_eq!(client.exchange_byte(0x02), Ok(0x22));
+        client.set_clock(SerialClock::Master).unwrap();
+        assert_eq!(client.exchange_byte(0x03), Ok(0x33));
+
+        let sc: Vec<u8> = bgb.received(104).iter().map(|p| p.extra1).collect();
+        assert_eq!(sc, vec![0x81, 0x80, 0x81]);
+    }
+
+    #[test]
+    fn set_paused_announces_status_flags() {
+        let bgb = FakeBgb::start(Vec::new());
+        let client = bgb.connect();
+
+        client.set_paused(true).unwrap();
+        client.set_paused(false).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while bgb.received(108).len() < 3 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let flags: Vec<u8> = bgb.received(108).iter().map(|p| p.data).collect();
+        // Handshake status first, then one per request
+        assert_eq!(flags, vec![0x01, 0x03, 0x01]);
+    }
+
+    #[test]
+    fn simultaneous_sync1_is_answered_and_used_as_response() {
+        let bgb = FakeBgb::start(vec![Reply::Simultaneous(0x77), Reply::Sync2(0x05)]);
+        let client = bgb.connect();
+
+        assert_eq!(client.exchange_byte(0x29), Ok(0x77));
+        // The next exchange proves the pending transfer was cleared
+        assert_eq!(client.exchange_byte(0x03), Ok(0x05));
+
+        // Our pending byte went back to BGB as the sync2 answer
+        let replies = bgb.received(105);
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].data, 0x29);
+    }
+
+    #[test]
+    fn sync1_timestamps_advance() {
+        let bgb = FakeBgb::start(Vec::new());
+        let client = bgb.connect();
+        for _ in 0..4 {
+            client.exchange_byte(0).unwrap();
+        }
+
+        let stamps: Vec<u32> = bgb.received(104).iter().map(|p| p.timestamp).collect();
+        assert_eq!(stamps.len(), 4);
+        assert!(stamps.windows(2).all(|w| w[1] > w[0]), "timestamps {:?}", stamps);
+    }
+}
@@ -1,8 +1,4 @@
-use std::sync::mpsc;
-use std::sync::atomic::AtomicBool;
-use std::sync::Arc;
-
-use crate::bgb::BgbClient;
+use crate::link::Link;
 
 /// Magic prefix used by the firmware for timing config and printer mode detection.
 /// 0xCAFE repeated 8 times + 0xDEADBEEF repeated 4 times = 32 bytes.
@@ -16,14 +12,20 @@ const MAGIC_PREFIX: [u8; 32] = [
 /// Printer mode magic suffix
 const PRINTER_SUFFIX: [u8; 4] = [b'P', b'R', b'N', b'T'];
 
+/// Raw byte bridge for binary WebSocket clients that speak the firmware's
+/// USB protocol directly.
 pub struct Bridge {
-    bgb: BgbClient,
+    bgb: Box<dyn Link>,
 }
 
 impl Bridge {
-    pub fn new(host: &str, port: u16, log_tx: Option<mpsc::Sender<String>>, verbose: Arc<AtomicBool>) -> Result<Self, String> {
-        let bgb = BgbClient::connect(host, port, log_tx, verbose)?;
-        Ok(Self { bgb })
+    pub fn new(bgb: Box<dyn Link>) -> Self {
+        Self { bgb }
+    }
+
+    /// Give back the link, e.g. to shut it down.
+    pub fn into_link(self) -> Box<dyn Link> {
+        self.bgb
     }
 
     /// Handle a binary message from the browser.
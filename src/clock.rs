@@ -0,0 +1,55 @@
+use std::time::{Duration, Instant};
+
+/// Where the game thread gets the time and how it waits, so tests can run
+/// its timers (grace periods, heartbeats, idle timeouts) without sleeping.
+pub trait Clock: Send {
+    fn now(&self) -> Instant;
+
+    fn sleep(&self, duration: Duration);
+
+    /// Time since `earlier` on this clock.
+    fn since(&self, earlier: Instant) -> Duration {
+        self.now().saturating_duration_since(earlier)
+    }
+}
+
+/// Wall-clock time and real sleeps.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// Test clock that only moves when told to; sleeping advances it at once.
+/// Clones share the same time.
+#[cfg(test)]
+#[derive(Clone)]
+pub struct MockClock(std::sync::Arc<std::sync::Mutex<Instant>>);
+
+#[cfg(test)]
+impl MockClock {
+    pub fn new() -> Self {
+        Self(std::sync::Arc::new(std::sync::Mutex::new(Instant::now())))
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.0.lock().unwrap() += duration;
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.0.lock().unwrap()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
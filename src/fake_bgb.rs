@@ -0,0 +1,90 @@
+//! Test-only stand-in for BGB's link cable server. Answers the version
+//! handshake, then replies to each sync1 from a script so tests can drive a
+//! real `BgbClient` over TCP.
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::bgb::{BgbClient, BgbEndpoint, PollInterval, Timeouts};
+use crate::protocol::{BgbPacket, StatusFlags};
+use crate::speed::Speed;
+use crate::verbosity::Verbosity;
+
+/// How the fake answers one sync1 from the client.
+#[derive(Debug, Clone, Copy)]
+pub enum Reply {
+    /// Normal transfer: sync2 carrying this byte
+    Sync2(u8),
+    /// Both sides clocking at once: answer with our own sync1 carrying this
+    /// byte, which the client must accept as its response
+    Simultaneous(u8),
+    /// Never answer, as if BGB lost the transfer
+    Silent,
+}
+
+pub struct FakeBgb {
+    pub endpoint: BgbEndpoint,
+    /// Every packet the client sent after the handshake, in order
+    pub received: Arc<Mutex<Vec<BgbPacket>>>,
+}
+
+impl FakeBgb {
+    /// Listen on a free local port and serve one client. Once `script` runs
+    /// out every sync1 is answered with `Sync2(0)`.
+    pub fn start(script: Vec<Reply>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = received.clone();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            serve(stream, script, &log);
+        });
+        Self {
+            endpoint: BgbEndpoint::Tcp { host: "127.0.0.1".into(), port },
+            received,
+        }
+    }
+
+    pub fn connect(&self) -> BgbClient {
+        BgbClient::connect(&self.endpoint, None, Verbosity::default(), Speed::default(),
+            PollInterval::default(), StatusFlags::default(), Timeouts::default()).unwrap()
+    }
+
+    /// Packets received so far with the given command.
+    pub fn received(&self, command: u8) -> Vec<BgbPacket> {
+        self.received.lock().unwrap().iter().filter(|p| p.command == command).copied().collect()
+    }
+}
+
+fn serve(mut stream: TcpStream, script: Vec<Reply>, log: &Mutex<Vec<BgbPacket>>) {
+    let Some(version) = read(&mut stream) else { return };
+    assert_eq!(version.command, 1, "client must open with a version packet");
+    write(&mut stream, BgbPacket::new(1, 1, 4, 0, 0));
+
+    let mut script = script.into_iter();
+    while let Some(pkt) = read(&mut stream) {
+        log.lock().unwrap().push(pkt);
+        if pkt.command != 104 {
+            continue;
+        }
+        let reply = match script.next().unwrap_or(Reply::Sync2(0)) {
+            Reply::Sync2(byte) => BgbPacket::new(105, byte, 0x80, 0, pkt.timestamp),
+            Reply::Simultaneous(byte) => BgbPacket::new(104, byte, 0x81, 0, pkt.timestamp),
+            Reply::Silent => continue,
+        };
+        write(&mut stream, reply);
+    }
+}
+
+fn read(stream: &mut TcpStream) -> Option<BgbPacket> {
+    let mut buf = [0u8; 8];
+    stream.read_exact(&mut buf).ok()?;
+    Some(BgbPacket::from_bytes(buf))
+}
+
+fn write(stream: &mut TcpStream, pkt: BgbPacket) {
+    let _ = stream.write_all(&pkt.to_bytes());
+}
@@ -1,16 +1,173 @@
+use std::sync::atomic::Ordering;
 use std::sync::mpsc;
-use std::time::Duration;
-use std::thread;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::bgb::BgbClient;
+use serde::{Deserialize, Serialize};
+
+use crate::bgb::{BgbVersion, LinkStats, PacketRecord, SerialClock, LATENCY_BUCKETS_MS};
+use crate::link::{ClosedLink, Link};
+use crate::clock::Clock;
+use crate::speed::Speed;
+use crate::verbosity::Verbosity;
+
+/// Byte pair used to check the Game Boy is on the link screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Probe {
+    /// Byte we send
+    pub send: u8,
+    /// Response the Game Boy gives when ready
+    pub expect: u8,
+}
+
+impl Default for Probe {
+    fn default() -> Self {
+        Self { send: 0x29, expect: 0x55 }
+    }
+}
+
+/// What the bridge knows about a supported game.
+#[derive(Debug)]
+pub struct GameProfile {
+    /// Name the browser uses in `set_game`
+    pub name: &'static str,
+    /// Default probe for this game
+    pub probe: Probe,
+    /// Byte to answer link transfers the Game Boy starts on its own
+    pub idle_reply: u8,
+    /// Music options as (name, byte sent during music select)
+    pub music: &'static [(&'static str, u8)],
+}
+
+/// Games the bridge knows by name.
+pub const PROFILES: &[GameProfile] = &[
+    // On the 2-player menu Tetris keeps answering the master byte with 0x55
+    GameProfile {
+        name: "tetris",
+        probe: Probe { send: 0x29, expect: 0x55 },
+        idle_reply: 0x00,
+        music: &[("a_type", 0x1C), ("b_type", 0x1D), ("c_type", 0x1E), ("off", 0x1F)],
+    },
+];
+
+/// Look up a game's profile by its `set_game` name.
+pub fn profile(name: &str) -> Option<&'static GameProfile> {
+    PROFILES.iter().find(|p| p.name.eq_ignore_ascii_case(name))
+}
+
+/// Most `RawByte` events emitted per second; the rest are counted and dropped.
+const RAW_BYTE_RATE_LIMIT: u32 = 100;
+
+/// Byte sent after the Game Boy reports its screen filled (0xFF).
+const FINAL_SCREEN: u8 = 0x43;
+
+/// In-game bytes with a fixed meaning, which no line count may use:
+/// heights below 20, win, lose and screen filled.
+fn is_reserved_game_byte(byte: u8) -> bool {
+    byte < 20 || matches!(byte, 0x77 | 0xAA | 0xFF)
+}
+
+/// Which byte carries each size of line attack, as (lines, byte) pairs.
+/// Decodes incoming `Lines` and encodes `QueueLines`, so the bridge and
+/// browser share one table. Defaults to 0x80 + n for n lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineMap(Vec<(u8, u8)>);
+
+impl Default for LineMap {
+    fn default() -> Self {
+        Self((0..=5).map(|lines| (lines, 0x80 + lines)).collect())
+    }
+}
+
+impl LineMap {
+    /// A map from `(lines, byte)` pairs. Each line count and byte may
+    /// appear once, and bytes can't be ones with a fixed in-game meaning.
+    pub fn new(entries: Vec<(u8, u8)>) -> Result<Self, String> {
+        for (i, &(lines, byte)) in entries.iter().enumerate() {
+            if is_reserved_game_byte(byte) {
+                return Err(format!("byte 0x{:02X} already has a meaning in game", byte));
+            }
+            if entries[..i].iter().any(|&(l, b)| l == lines || b == byte) {
+                return Err(format!("{} lines or byte 0x{:02X} mapped twice", lines, byte));
+            }
+        }
+        Ok(Self(entries))
+    }
+
+    pub fn entries(&self) -> &[(u8, u8)] {
+        &self.0
+    }
+
+    fn byte_for(&self, lines: u8) -> Option<u8> {
+        self.0.iter().find(|&&(l, _)| l == lines).map(|&(_, b)| b)
+    }
+
+    fn lines_for(&self, byte: u8) -> Option<u8> {
+        self.0.iter().find(|&&(_, b)| b == byte).map(|&(l, _)| l)
+    }
+}
+
+/// Longest byte sequence accepted from the browser in a single command
+/// (`start_game` garbage/tiles, `send_seq`).
+pub const MAX_SEQUENCE_LEN: usize = 1024;
+/// Opens a fresh link for `ReconnectBgb`, with the BGB version when it
+/// connected right away.
+pub type LinkFactory = Box<dyn Fn() -> Result<(Box<dyn Link>, Option<BgbVersion>), String> + Send>;
+
+/// In-game exchange interval at 1x speed.
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+/// Consecutive in-game ticks whose exchange outlasted the tick before
+/// `SlowLink` is reported.
+const SLOW_LINK_TICKS: u32 = 10;
+/// Minimum time between `SlowLink` reports.
+const SLOW_LINK_REPEAT: Duration = Duration::from_secs(30);
+/// In-game ticks measured per `Timing` report (5 s at 1x speed).
+const TIMING_REPORT_TICKS: usize = 50;
+/// Longest delay a `TimedSequence` step may ask for; longer ones are clamped.
+pub const MAX_STEP_DELAY_MS: u64 = 1000;
+/// Longest a `TimedSequence` may run after clamping. The game thread does
+/// nothing else meanwhile, so longer scripts are rejected.
+pub const MAX_TIMED_SEQUENCE_MS: u64 = 10_000;
+
+/// How the game loop chooses between queued commands and the opponent height.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QueuePolicy {
+    /// Drain the command queue before sending any height (can starve heights)
+    #[default]
+    Strict,
+    /// Alternate a queued command and a height each tick while both are pending
+    Interleaved,
+    /// Send the height whenever it changed since last sent, queued commands otherwise
+    HeightFirst,
+}
+
+impl QueuePolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "strict" => Some(QueuePolicy::Strict),
+            "interleaved" => Some(QueuePolicy::Interleaved),
+            "height_first" => Some(QueuePolicy::HeightFirst),
+            _ => None,
+        }
+    }
+
+    /// The name `from_name` accepts.
+    pub fn name(self) -> &'static str {
+        match self {
+            QueuePolicy::Strict => "strict",
+            QueuePolicy::Interleaved => "interleaved",
+            QueuePolicy::HeightFirst => "height_first",
+        }
+    }
+}
 
 // ── Messages between WebSocket thread and game thread ──────────────────
 
 /// Commands sent from the WebSocket thread to the game thread.
 #[derive(Debug)]
 pub enum GameCommand {
-    /// Tell the bridge what game to play (e.g. "tetris")
-    SetGame(String),
+    /// Tell the bridge what game to play (e.g. "tetris") and how to probe for it
+    SetGame { game: String, probe: Probe },
     /// Set the music selection byte
     SetMusic(u8),
     /// Confirm music selection (transitions to waiting for start_game)
@@ -25,6 +182,62 @@ pub enum GameCommand {
     SetHeight(u8),
     /// Queue a win/lose/lines command to send to the Game Boy
     QueueCommand(u8),
+    /// Queue a line attack of this many lines, encoded through the line map
+    QueueLines(u8),
+    /// Replace the line map
+    SetLineMap(LineMap),
+    /// Queue the final-screen byte (0x43) that follows a screen-filled signal
+    SendFinalScreen,
+    /// Send this byte every in-game tick instead of heights and queued
+    /// commands (None restores normal behaviour). Developer diagnostic.
+    SetLoopOverride(Option<u8>),
+    /// Change how queued commands are prioritised against heights
+    SetQueuePolicy(QueuePolicy),
+    /// Change the heartbeat interval (None disables heartbeats)
+    SetHeartbeat(Option<Duration>),
+    /// Set the emulation speed multiplier (clamped)
+    SetSpeed(f32),
+    /// Apply the fields present in a `set_config` command
+    SetConfig(ConfigUpdate),
+    /// Browser finished its end-of-match sequence; wait for the next start_game
+    EndMatch,
+    /// Report the opponent height currently being sent each tick
+    GetHeight,
+    /// Report the effective configuration, defaults and overrides alike
+    GetConfig,
+    /// Report the current phase, game and heights, e.g. after a browser reconnects
+    QueryStatus,
+    /// Report the games in `PROFILES` and their music options
+    ListGames,
+    /// Report how the link's exchange latencies are distributed
+    LatencyHistogram,
+    /// Report the link's recent packet history
+    GetPackets,
+    /// Choose which verbose log categories are enabled (`Verbosity` bits)
+    SetLogMask(u8),
+    /// Drop the BGB connection and open a new one, then start over from
+    /// probing the game (or waiting for one)
+    ReconnectBgb,
+    /// Report the phase, game, music, height and command queue as JSON
+    SnapshotState,
+    /// Put back state from an earlier `SnapshotState`
+    RestoreState(String),
+    /// Debug shortcut: jump straight to the game loop without probing,
+    /// music selection or the start sequence. Only accepted while game
+    /// verbose logging is on.
+    ForceInGame,
+    /// Run the probe exchange once and report the result, without changing phase
+    SelfTest,
+    /// Flush the link's in-flight transfer after a desync, keeping the connection
+    ResetLink,
+    /// Switch between internal (master) and external (slave) link clock
+    SetClock(SerialClock),
+    /// Pause or resume BGB's emulation
+    SetEmuPaused(bool),
+    /// Exchange each byte in turn and report all responses, outside any game logic
+    SendSequence(Vec<u8>),
+    /// Send each byte and wait its delay in ms, like the start sequence
+    TimedSequence(Vec<(u8, u64)>),
     /// Stop the game thread
     Stop,
 }
@@ -34,20 +247,159 @@ pub enum GameCommand {
 pub enum GameEvent {
     /// BGB probe succeeded (0x29→0x55), ready for music
     Connected,
+    /// Start sequence finished; the match is being played
+    GameStarted,
+    /// One in-game exchange, when `echo_raw_bytes` is enabled
+    RawByte { sent: u8, received: u8 },
+    /// Current loop override, in reply to `SetLoopOverride`
+    LoopOverride(Option<u8>),
+    /// Music selection byte now being sent, in reply to `SetMusic`
+    MusicSet(u8),
     /// Height value read from the Game Boy
     Height(u8),
-    /// Lines signal from the Game Boy (0x80..0x85)
-    Lines(u8),
+    /// Lines signal from the Game Boy: the byte and, through the line
+    /// map, how many lines it stands for
+    Lines { value: u8, count: u8 },
     /// Game Boy reports 30 lines reached (0x77) — we win
     Win,
     /// Game Boy reports topped out (0xAA) — we lose
     Lose,
     /// Game Boy reports screen filled after loss (0xFF)
     ScreenFilled,
+    /// Match state cleared after `EndMatch`, ready for the next start_game
+    MatchEnded,
+    /// Something the browser should surface to the user
+    Warning(String),
+    /// A command was rejected
+    Error(String),
+    /// The game thread stopped itself after `idle_timeout` without activity
+    IdleTimeout,
+    /// A match outlasted `max_session` and was abandoned; the thread is
+    /// waiting for a game again
+    SessionTimeout { limit: Duration },
+    /// A run of unrecognized bytes suggests the link lost sync
+    Desync { count: u32 },
+    /// Measured in-game tick period over the last `TIMING_REPORT_TICKS`
+    /// ticks: the mean and its standard deviation
+    Timing { target_ms: f64, actual_ms: f64, jitter_ms: f64 },
+    /// Exchanges keep taking longer than the tick, so the match will lag;
+    /// BGB is likely running below full speed
+    SlowLink { latency_ms: u64, tick_ms: u64 },
+    /// An in-game byte matching no known signal, when `report_unhandled` is enabled
+    Unhandled(u8),
+    /// Current opponent height, in reply to `GetHeight`
+    HeightEcho(u8),
+    /// Exchanges per `LATENCY_BUCKETS_MS` bucket, in reply to `LatencyHistogram`
+    LatencyHistogram([u64; LATENCY_BUCKETS_MS.len() + 1]),
+    /// Effective settings, in reply to `GetConfig`
+    Config(ConfigSnapshot),
+    /// JSON for `RestoreState`, in reply to `SnapshotState`
+    Snapshot(String),
+    /// Snapshot of the game thread's state, in reply to `QueryStatus`
+    Status { phase: &'static str, game: String, music: u8, height: u8, game_ended: bool },
+    /// A queued command byte has just been exchanged with the Game Boy
+    CommandSent(u8),
+    /// BGB was asked to pause (true) or resume (false), in reply to `SetEmuPaused`
+    EmuPaused(bool),
+    /// Recent BGB packets, oldest first, in reply to `GetPackets`
+    Packets(Vec<PacketRecord>),
+    /// Supported games, in reply to `ListGames`
+    Games(&'static [GameProfile]),
+    /// Result of a `SelfTest` command
+    SelfTestResult(SelfTestResult),
+    /// Summary of the wrong answers seen after repeated failed probes
+    ProbeDiagnostic(ProbeDiagnostic),
+    /// Responses to a `SendSequence`, one per byte sent
+    SequenceResponse(Vec<u8>),
+    /// `sent` of `total` steps of a `TimedSequence` have been played
+    TimedSequenceProgress { sent: usize, total: usize },
+    /// A `TimedSequence` finished
+    TimedSequenceDone { steps: usize },
+    /// Periodic liveness signal with the current phase name and thread uptime
+    Heartbeat { phase: &'static str, uptime_ms: u64 },
+    /// The link was closed for `ReconnectBgb`
+    BgbDisconnected,
+    /// `ReconnectBgb` opened a new link. Carries its counters so the owner
+    /// can follow the new connection.
+    BgbConnected { version: Option<BgbVersion>, stats: Arc<LinkStats> },
     /// Log message
     Log(String),
 }
 
+/// Outcome of a one-off probe exchange.
+#[derive(Debug, Clone)]
+pub struct SelfTestResult {
+    /// The Game Boy answered the probe with the expected byte
+    pub ok: bool,
+    /// Byte the Game Boy answered with, None if the exchange failed
+    pub response_byte: Option<u8>,
+    /// Byte we were hoping for
+    pub expected: u8,
+    pub latency_ms: u64,
+}
+
+impl std::fmt::Display for SelfTestResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.response_byte {
+            Some(b) if self.ok => write!(f, "Self-test OK: 0x{:02X} in {}ms", b, self.latency_ms),
+            Some(b) => write!(f, "Self-test failed: got 0x{:02X}, expected 0x{:02X} ({}ms)",
+                b, self.expected, self.latency_ms),
+            None => write!(f, "Self-test failed: no response after {}ms", self.latency_ms),
+        }
+    }
+}
+
+/// Tally of unexpected probe responses since the last success or report.
+#[derive(Debug, Clone)]
+pub struct ProbeDiagnostic {
+    pub expected: u8,
+    /// Each distinct response with how often it was seen, in first-seen
+    /// order. None counts failed exchanges (timeouts).
+    pub seen: Vec<(Option<u8>, u32)>,
+}
+
+impl ProbeDiagnostic {
+    fn record(&mut self, response: Option<u8>) {
+        match self.seen.iter_mut().find(|(b, _)| *b == response) {
+            Some((_, count)) => *count += 1,
+            None => self.seen.push((response, 1)),
+        }
+    }
+
+    fn total(&self) -> u32 {
+        self.seen.iter().map(|(_, count)| count).sum()
+    }
+}
+
+impl std::fmt::Display for ProbeDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "expected 0x{:02X}, saw ", self.expected)?;
+        for (i, (byte, count)) in self.seen.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            match byte {
+                Some(b) => write!(f, "0x{:02X} x{}", b, count)?,
+                None => write!(f, "timeout x{}", count)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Send the probe byte once and time the round trip. Used by the game
+/// thread and by the GUI's "Test Connection" button when no browser is attached.
+pub fn self_test(bgb: &dyn Link, probe: Probe) -> SelfTestResult {
+    let started = Instant::now();
+    let response = bgb.exchange_byte(probe.send).ok();
+    SelfTestResult {
+        ok: response == Some(probe.expect),
+        response_byte: response,
+        expected: probe.expect,
+        latency_ms: started.elapsed().as_millis() as u64,
+    }
+}
+
 // ── Game phases ────────────────────────────────────────────────────────
 
 #[derive(Debug, Clone, PartialEq)]
@@ -66,39 +418,322 @@ enum Phase {
     InGame,
 }
 
+impl Phase {
+    /// Name used in browser-bound events.
+    fn name(&self) -> &'static str {
+        match self {
+            Phase::WaitingForGame => "waiting_for_game",
+            Phase::Probing => "probing",
+            Phase::MusicSelect => "music_select",
+            Phase::WaitingForStart => "waiting_for_start",
+            Phase::GameStarting => "game_starting",
+            Phase::InGame => "in_game",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Phase> {
+        [Phase::WaitingForGame, Phase::Probing, Phase::MusicSelect, Phase::WaitingForStart, Phase::GameStarting, Phase::InGame]
+            .into_iter()
+            .find(|phase| phase.name() == name)
+    }
+}
+
+/// The game thread's logical state, as `SnapshotState` reports it and
+/// `RestoreState` puts it back.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct StateSnapshot {
+    phase: String,
+    game: String,
+    music: u8,
+    height: u8,
+    queue: Vec<u8>,
+}
+
+// ── Configuration ──────────────────────────────────────────────────────
+
+/// Tunables for the game thread.
+#[derive(Debug, Clone)]
+pub struct GameConfig {
+    /// How often to emit `GameEvent::Heartbeat`. None disables heartbeats.
+    pub heartbeat_interval: Option<Duration>,
+    /// Consecutive unrecognized in-game bytes before reporting a desync. 0 disables.
+    pub desync_threshold: u32,
+    /// Maximum queued commands; further `QueueCommand`s are rejected
+    pub queue_limit: usize,
+    /// Stop the game thread after this long without browser commands while
+    /// waiting for a game or start. None disables.
+    pub idle_timeout: Option<Duration>,
+    /// End a match that has run this long and go back to waiting for a
+    /// game, so a stuck match can't hold BGB forever. None disables.
+    pub max_session: Option<Duration>,
+    /// Failed probes between `ProbeDiagnostic` reports. 0 disables.
+    pub probe_diagnostic_after: u32,
+    /// Queue the final-screen byte as soon as the Game Boy reports the screen
+    /// filled. Off by default: the browser sends `final_screen` itself.
+    pub auto_final_screen: bool,
+    /// Emit `RawByte` for every in-game exchange (debugging, rate limited)
+    pub echo_raw_bytes: bool,
+    /// Emit `Unhandled` for every in-game byte matching no known signal
+    /// (reverse-engineering aid)
+    pub report_unhandled: bool,
+    /// Wait before re-probing after a wrong answer
+    pub probe_retry: Duration,
+    /// Wait before re-probing after a failed exchange
+    pub probe_error_retry: Duration,
+    /// Pacing of the garbage and tile bytes in the game start sequence
+    pub start_timing: StartTiming,
+    /// Pace ticks by the speed in extra1 of BGB's status packets, whenever
+    /// it reports one, instead of the user's speed multiplier. Off by
+    /// default: BGB documents the field as unused.
+    pub follow_bgb_speed: bool,
+}
+
+/// How the game start sequence paces its garbage and tile bytes: `burst`
+/// bytes at a time, each burst followed by that kind's delay. Fast BGB
+/// setups can take shorter delays or bigger bursts; slow ones need longer
+/// delays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StartTiming {
+    pub garbage_delay_ms: u64,
+    pub tile_delay_ms: u64,
+    pub burst: usize,
+}
+
+impl Default for StartTiming {
+    fn default() -> Self {
+        Self { garbage_delay_ms: 4, tile_delay_ms: 4, burst: 1 }
+    }
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat_interval: Some(Duration::from_secs(2)),
+            desync_threshold: 10,
+            queue_limit: 32,
+            idle_timeout: None,
+            max_session: None,
+            probe_diagnostic_after: 4,
+            auto_final_screen: false,
+            echo_raw_bytes: false,
+            report_unhandled: false,
+            probe_retry: Duration::from_millis(500),
+            probe_error_retry: Duration::from_millis(1000),
+            start_timing: StartTiming::default(),
+            follow_bgb_speed: false,
+        }
+    }
+}
+
+/// Everything the browser can tune on a running game thread, in reply to
+/// `GetConfig`.
+#[derive(Debug, Clone)]
+pub struct ConfigSnapshot {
+    pub config: GameConfig,
+    /// In-game tick interval at the current speed
+    pub tick: Duration,
+    pub speed: f32,
+    pub queue_policy: QueuePolicy,
+    pub loop_override: Option<u8>,
+    /// Byte answering BGB-initiated transfers, if the game sets one
+    pub idle_reply: Option<u8>,
+    /// Enabled `Verbosity` categories
+    pub log_mask: u8,
+}
+
+/// Partial update to `GameConfig`; absent fields are left unchanged.
+#[derive(Debug, Default)]
+pub struct ConfigUpdate {
+    pub desync_threshold: Option<u32>,
+    pub queue_limit: Option<usize>,
+    /// `Some(None)` disables the idle timeout
+    pub idle_timeout: Option<Option<Duration>>,
+    /// `Some(None)` disables the match duration limit
+    pub max_session: Option<Option<Duration>>,
+    pub probe_diagnostic_after: Option<u32>,
+    pub auto_final_screen: Option<bool>,
+    pub echo_raw_bytes: Option<bool>,
+    pub report_unhandled: Option<bool>,
+    pub probe_retry: Option<Duration>,
+    pub probe_error_retry: Option<Duration>,
+    pub garbage_delay_ms: Option<u64>,
+    pub tile_delay_ms: Option<u64>,
+    pub start_burst: Option<usize>,
+    pub follow_bgb_speed: Option<bool>,
+}
+
+impl GameConfig {
+    fn apply(&mut self, update: ConfigUpdate) {
+        if let Some(n) = update.desync_threshold {
+            self.desync_threshold = n;
+        }
+        if let Some(n) = update.queue_limit {
+            self.queue_limit = n;
+        }
+        if let Some(timeout) = update.idle_timeout {
+            self.idle_timeout = timeout;
+        }
+        if let Some(limit) = update.max_session {
+            self.max_session = limit;
+        }
+        if let Some(n) = update.probe_diagnostic_after {
+            self.probe_diagnostic_after = n;
+        }
+        if let Some(auto) = update.auto_final_screen {
+            self.auto_final_screen = auto;
+        }
+        if let Some(echo) = update.echo_raw_bytes {
+            self.echo_raw_bytes = echo;
+        }
+        if let Some(report) = update.report_unhandled {
+            self.report_unhandled = report;
+        }
+        if let Some(d) = update.probe_retry {
+            self.probe_retry = d;
+        }
+        if let Some(d) = update.probe_error_retry {
+            self.probe_error_retry = d;
+        }
+        if let Some(follow) = update.follow_bgb_speed {
+            self.follow_bgb_speed = follow;
+        }
+        if let Some(ms) = update.garbage_delay_ms {
+            self.start_timing.garbage_delay_ms = ms.min(MAX_STEP_DELAY_MS);
+        }
+        if let Some(ms) = update.tile_delay_ms {
+            self.start_timing.tile_delay_ms = ms.min(MAX_STEP_DELAY_MS);
+        }
+        if let Some(n) = update.start_burst {
+            self.start_timing.burst = n.max(1);
+        }
+    }
+}
+
 // ── Game thread ────────────────────────────────────────────────────────
 
 pub struct GameThread {
-    bgb: BgbClient,
+    bgb: Box<dyn Link>,
     cmd_rx: mpsc::Receiver<GameCommand>,
     event_tx: mpsc::Sender<GameEvent>,
+    config: GameConfig,
+    speed: Speed,
+    /// Counters of `bgb`, polled for the emulation speed BGB reports and
+    /// exchange latency
+    link_stats: Arc<LinkStats>,
+    /// Last speed reported by BGB, in quarters (0 = none reported)
+    reported_speed: u8,
+    verbose: Verbosity,
     phase: Phase,
+    /// Game name from the last `SetGame`
+    game: String,
+    probe: Probe,
+    /// Wrong probe answers not yet reported
+    probe_misses: ProbeDiagnostic,
+    /// Earliest time for the next probe attempt
+    next_probe_at: Instant,
     music_byte: u8,
     opponent_height: u8,
+    /// Height set while waiting for start_game, used instead of 0 when it arrives
+    seeded_height: Option<u8>,
+    line_map: LineMap,
     command_queue: Vec<u8>,
-    game_started_at: Option<std::time::Instant>,
+    queue_policy: QueuePolicy,
+    /// Whether the last in-game tick sent a queued command (for `Interleaved`)
+    sent_queued_last: bool,
+    /// Height most recently sent to the Game Boy (for `HeightFirst`)
+    last_sent_height: Option<u8>,
+    /// Fixed byte sent every in-game tick, bypassing queue and height
+    loop_override: Option<u8>,
+    /// Rate limiting for `RawByte` events
+    raw_window_start: Instant,
+    raw_in_window: u32,
+    raw_dropped: u32,
+    game_started_at: Option<Instant>,
+    /// Latched once Win or Lose is emitted; cleared by the next StartGame or EndMatch.
+    /// Suppresses repeated end signals within the same match.
     game_ended: bool,
+    /// Consecutive in-game bytes that matched no known signal
+    unrecognized_run: u32,
+    /// Consecutive in-game ticks whose exchange outlasted the tick
+    slow_ticks: u32,
+    last_slow_warning: Option<Instant>,
+    /// Start of the previous in-game tick, and the periods measured since
+    /// the last `Timing` report
+    last_tick_at: Option<Instant>,
+    tick_periods: Vec<Duration>,
+    started_at: Instant,
+    last_heartbeat: Instant,
+    /// Last browser command, for the idle timeout
+    last_activity: Instant,
+    /// How to open a new link for `ReconnectBgb`, if the owner supports it
+    reconnect: Option<LinkFactory>,
+    /// Time source for every timer above; a mock in tests
+    clock: Box<dyn Clock>,
 }
 
 impl GameThread {
     pub fn new(
-        bgb: BgbClient,
+        bgb: Box<dyn Link>,
         cmd_rx: mpsc::Receiver<GameCommand>,
         event_tx: mpsc::Sender<GameEvent>,
+        config: GameConfig,
+        speed: Speed,
+        verbose: Verbosity,
+        clock: Box<dyn Clock>,
     ) -> Self {
+        let now = clock.now();
+        let link_stats = bgb.stats();
         Self {
             bgb,
             cmd_rx,
             event_tx,
+            config,
+            speed,
+            link_stats,
+            reported_speed: 0,
+            verbose,
             phase: Phase::WaitingForGame,
+            game: String::new(),
+            probe: Probe::default(),
+            probe_misses: ProbeDiagnostic { expected: Probe::default().expect, seen: Vec::new() },
+            next_probe_at: now,
             music_byte: 0x1C, // default: A-Type music
             opponent_height: 0,
+            seeded_height: None,
+            line_map: LineMap::default(),
             command_queue: Vec::new(),
+            queue_policy: QueuePolicy::default(),
+            sent_queued_last: false,
+            last_sent_height: None,
+            loop_override: None,
+            raw_window_start: now,
+            raw_in_window: 0,
+            raw_dropped: 0,
             game_started_at: None,
             game_ended: false,
+            unrecognized_run: 0,
+            slow_ticks: 0,
+            last_slow_warning: None,
+            last_tick_at: None,
+            tick_periods: Vec::new(),
+            started_at: now,
+            last_heartbeat: now,
+            last_activity: now,
+            reconnect: None,
+            clock,
         }
     }
 
+    /// Let `ReconnectBgb` replace the link with one from `factory`.
+    pub fn set_reconnect(&mut self, factory: LinkFactory) {
+        self.reconnect = Some(factory);
+    }
+
+    /// Give back the link once the thread has stopped running.
+    pub fn into_link(self) -> Box<dyn Link> {
+        self.bgb
+    }
+
     /// Run the game thread. Blocks until stopped or BGB disconnects.
     pub fn run(&mut self) {
         self.log("Game thread started");
@@ -109,28 +744,56 @@ impl GameThread {
                 return;
             }
 
+            self.maybe_send_heartbeat();
+            self.follow_reported_speed();
+
+            if self.idle_timed_out() {
+                self.log("Idle timeout reached, stopping game thread");
+                self.send_event(GameEvent::IdleTimeout);
+                return;
+            }
+            self.end_overlong_match();
+
+            // Tick timing only spans consecutive in-game ticks
+            if self.phase != Phase::InGame {
+                self.last_tick_at = None;
+                self.tick_periods.clear();
+            }
+
             // Run the current phase
             match self.phase {
                 Phase::WaitingForGame => {
-                    thread::sleep(Duration::from_millis(50));
+                    self.clock.sleep(Duration::from_millis(50));
                 }
                 Phase::Probing => {
-                    self.run_probe();
+                    // Retries are paced here rather than by sleeping in
+                    // run_probe, so commands stay responsive between attempts
+                    if self.clock.now() >= self.next_probe_at {
+                        self.run_probe();
+                    } else {
+                        self.clock.sleep(Duration::from_millis(10));
+                    }
                 }
                 Phase::MusicSelect => {
                     self.run_music_exchange();
-                    thread::sleep(Duration::from_millis(100));
+                    self.clock.sleep(Duration::from_millis(100));
                 }
                 Phase::WaitingForStart => {
-                    thread::sleep(Duration::from_millis(50));
+                    self.clock.sleep(Duration::from_millis(50));
                 }
                 Phase::GameStarting => {
                     // Handled by start_game command processing
-                    thread::sleep(Duration::from_millis(50));
+                    self.clock.sleep(Duration::from_millis(50));
                 }
                 Phase::InGame => {
+                    let tick_start = self.clock.now();
+                    self.record_tick(tick_start);
                     self.run_game_loop_tick();
-                    thread::sleep(Duration::from_millis(100));
+                    self.check_link_latency();
+                    // Sleep out the rest of the tick, so the exchange's own
+                    // duration doesn't stretch the period
+                    let period = self.tick_interval();
+                    self.clock.sleep(period.saturating_sub(self.clock.since(tick_start)));
                 }
             }
         }
@@ -141,36 +804,226 @@ impl GameThread {
         // Drain all pending commands
         loop {
             match self.cmd_rx.try_recv() {
-                Ok(cmd) => match cmd {
-                    GameCommand::SetGame(game) => {
-                        self.log(&format!("Game set to: {}", game));
-                        self.phase = Phase::Probing;
-                    }
-                    GameCommand::SetMusic(byte) => {
-                        self.music_byte = byte;
-                    }
-                    GameCommand::ConfirmMusic => {
-                        self.log("Music confirmed");
-                        // Send 0x50 to confirm music selection
-                        let _ = self.exchange(0x50);
-                        self.phase = Phase::WaitingForStart;
-                    }
-                    GameCommand::StartGame { garbage, tiles, is_first } => {
-                        self.log(&format!("Starting game (first={}, garbage={}, tiles={})",
-                            is_first, garbage.len(), tiles.len()));
-                        self.run_game_start_sequence(&garbage, &tiles, is_first);
-                    }
-                    GameCommand::SetHeight(h) => {
-                        self.opponent_height = h;
+                Ok(cmd) => {
+                    self.last_activity = self.clock.now();
+                    match cmd {
+                        GameCommand::SetGame { game, probe } => {
+                            // Not while a start sequence or match is running
+                            let valid = !matches!(self.phase, Phase::GameStarting | Phase::InGame);
+                            if self.check_phase("set_game", valid) {
+                                self.log(&format!("Game set to: {}", game));
+                                if let Some(profile) = profile(&game) {
+                                    self.log(&format!("Answering idle transfers with 0x{:02X}", profile.idle_reply));
+                                    self.bgb.set_idle_reply(profile.idle_reply);
+                                }
+                                self.game = game;
+                                self.probe = probe;
+                                self.bgb.set_probe(probe);
+                                self.probe_misses = ProbeDiagnostic { expected: probe.expect, seen: Vec::new() };
+                                self.next_probe_at = self.clock.now();
+                                self.phase = Phase::Probing;
+                            }
+                        }
+                        GameCommand::SetMusic(byte) => {
+                            // Accepted in any phase: a selection made before the
+                            // probe succeeds is what MusicSelect starts sending
+                            self.music_byte = byte;
+                            self.send_event(GameEvent::MusicSet(byte));
+                        }
+                        GameCommand::ConfirmMusic => {
+                            if self.check_phase("confirm_music", self.phase == Phase::MusicSelect) {
+                                // Send 0x50 to confirm music selection. If it never
+                                // reached the Game Boy, stay put so the browser can retry.
+                                match self.exchange(0x50) {
+                                    Ok(_) => {
+                                        self.log("Music confirmed");
+                                        self.phase = Phase::WaitingForStart;
+                                    }
+                                    Err(e) => {
+                                        let msg = format!("confirm_music failed: {}", e);
+                                        self.log(&msg);
+                                        self.send_event(GameEvent::Error(msg));
+                                    }
+                                }
+                            }
+                        }
+                        GameCommand::StartGame { garbage, tiles, is_first } => {
+                            // A finished match may go straight into a rematch without end_match
+                            let valid = self.phase == Phase::WaitingForStart
+                                || (self.phase == Phase::InGame && self.game_ended);
+                            if garbage.len() > MAX_SEQUENCE_LEN || tiles.len() > MAX_SEQUENCE_LEN {
+                                self.send_event(GameEvent::Error(format!(
+                                    "start_game data longer than {} bytes", MAX_SEQUENCE_LEN)));
+                            } else if self.check_phase("start_game", valid) {
+                                self.log(&format!("Starting game (first={}, garbage={}, tiles={})",
+                                    is_first, garbage.len(), tiles.len()));
+                                self.run_game_start_sequence(&garbage, &tiles, is_first);
+                            }
+                        }
+                        GameCommand::SetHeight(h) => {
+                            // Before a match this seeds the starting height (resume or handicap)
+                            if self.phase == Phase::WaitingForStart {
+                                self.seeded_height = Some(h);
+                            }
+                            self.opponent_height = h;
+                        }
+                        GameCommand::QueueCommand(cmd) => {
+                            self.enqueue(cmd);
+                        }
+                        GameCommand::QueueLines(lines) => match self.line_map.byte_for(lines) {
+                            Some(byte) => self.enqueue(byte),
+                            None => {
+                                let msg = format!("queue_lines: no byte mapped for {} lines", lines);
+                                self.log(&msg);
+                                self.send_event(GameEvent::Error(msg));
+                            }
+                        },
+                        GameCommand::SetLineMap(map) => {
+                            self.log(&format!("Line map set: {:?}", map.entries()));
+                            self.line_map = map;
+                        }
+                        GameCommand::SendFinalScreen => {
+                            self.enqueue(FINAL_SCREEN);
+                        }
+                        GameCommand::SetLoopOverride(value) => {
+                            match value {
+                                Some(b) => self.log(&format!("Loop override: sending 0x{:02X} every tick", b)),
+                                None => self.log("Loop override cleared"),
+                            }
+                            self.loop_override = value;
+                            self.send_event(GameEvent::LoopOverride(value));
+                        }
+                        GameCommand::SetQueuePolicy(policy) => {
+                            self.log(&format!("Queue policy set to {:?}", policy));
+                            self.queue_policy = policy;
+                        }
+                        GameCommand::SetHeartbeat(interval) => {
+                            self.config.heartbeat_interval = interval;
+                            self.last_heartbeat = self.clock.now();
+                        }
+                        GameCommand::SetSpeed(multiplier) => {
+                            self.speed.set(multiplier);
+                            self.log(&format!("Speed multiplier set to {:.2}", self.speed.get()));
+                        }
+                        GameCommand::EndMatch => {
+                            if self.check_phase("end_match", self.phase == Phase::InGame) {
+                                self.log("Match over, waiting for next start");
+                                self.phase = Phase::WaitingForStart;
+                                self.game_ended = false;
+                                self.command_queue.clear();
+                                self.send_event(GameEvent::MatchEnded);
+                                // A lazily connected BGB is freed until the next game
+                                self.bgb.release();
+                            }
+                        }
+                        GameCommand::SetLogMask(mask) => {
+                            self.verbose.set(mask);
+                            self.log(&format!("Verbose log mask set to 0x{:02X}", self.verbose.get()));
+                        }
+                        GameCommand::GetPackets => {
+                            self.send_event(GameEvent::Packets(self.bgb.recent_packets()));
+                        }
+                        GameCommand::GetConfig => {
+                            self.send_event(GameEvent::Config(ConfigSnapshot {
+                                config: self.config.clone(),
+                                tick: self.tick_interval(),
+                                speed: self.speed.get(),
+                                queue_policy: self.queue_policy,
+                                loop_override: self.loop_override,
+                                idle_reply: profile(&self.game).map(|p| p.idle_reply),
+                                log_mask: self.verbose.get(),
+                            }));
+                        }
+                        GameCommand::SnapshotState => {
+                            self.send_event(GameEvent::Snapshot(self.snapshot_state()));
+                        }
+                        GameCommand::RestoreState(json) => match self.restore_state(&json) {
+                            Ok(()) => self.log(&format!("State restored: {}", self.phase.name())),
+                            Err(e) => {
+                                let msg = format!("restore_state failed: {}", e);
+                                self.log(&msg);
+                                self.send_event(GameEvent::Error(msg));
+                            }
+                        },
+                        GameCommand::QueryStatus => {
+                            self.send_event(GameEvent::Status {
+                                phase: self.phase.name(),
+                                game: self.game.clone(),
+                                music: self.music_byte,
+                                height: self.opponent_height,
+                                game_ended: self.game_ended,
+                            });
+                        }
+                        GameCommand::ListGames => {
+                            self.send_event(GameEvent::Games(PROFILES));
+                        }
+                        GameCommand::LatencyHistogram => {
+                            self.send_event(GameEvent::LatencyHistogram(self.link_stats.latency_histogram()));
+                        }
+                        GameCommand::GetHeight => {
+                            self.send_event(GameEvent::HeightEcho(self.opponent_height));
+                        }
+                        GameCommand::SelfTest => {
+                            // The probe byte would land in the start sequence or match
+                            let valid = matches!(self.phase, Phase::WaitingForGame | Phase::Probing | Phase::MusicSelect);
+                            if self.check_phase("selftest", valid) {
+                                let result = self_test(&*self.bgb, self.probe);
+                                self.log(&result.to_string());
+                                self.send_event(GameEvent::SelfTestResult(result));
+                            }
+                        }
+                        GameCommand::ReconnectBgb => self.reconnect_bgb(),
+                        GameCommand::ForceInGame => self.force_in_game(),
+                        GameCommand::ResetLink => {
+                            match self.bgb.reset_link() {
+                                Ok(dropped) => {
+                                    self.unrecognized_run = 0;
+                                    self.log(&format!("Link reset, flushed {} stale response(s)", dropped));
+                                }
+                                Err(e) => self.send_event(GameEvent::Error(format!("reset_link failed: {}", e))),
+                            }
+                        }
+                        GameCommand::SetEmuPaused(paused) => {
+                            match self.bgb.set_paused(paused) {
+                                Ok(()) => self.send_event(GameEvent::EmuPaused(paused)),
+                                Err(e) => self.send_event(GameEvent::Error(format!("emu_pause failed: {}", e))),
+                            }
+                        }
+                        GameCommand::SetClock(clock) => {
+                            if let Err(e) = self.bgb.set_clock(clock) {
+                                self.send_event(GameEvent::Error(format!("set_clock failed: {}", e)));
+                            }
+                        }
+                        GameCommand::SendSequence(bytes) => {
+                            // Raw bytes would desync a start sequence or match
+                            let valid = !matches!(self.phase, Phase::GameStarting | Phase::InGame);
+                            if bytes.len() > MAX_SEQUENCE_LEN {
+                                self.send_event(GameEvent::Error(format!(
+                                    "send_seq longer than {} bytes", MAX_SEQUENCE_LEN)));
+                            } else if self.check_phase("send_seq", valid) {
+                                self.vlog(&format!("Sending {} byte sequence", bytes.len()));
+                                match self.exchange_seq(&bytes, 4) {
+                                    Ok(responses) => self.send_event(GameEvent::SequenceResponse(responses)),
+                                    Err(e) => self.send_event(GameEvent::Error(format!("send_seq failed: {}", e))),
+                                }
+                            }
+                        }
+                        GameCommand::TimedSequence(steps) => {
+                            let valid = !matches!(self.phase, Phase::GameStarting | Phase::InGame);
+                            if self.check_phase("timed_seq", valid) {
+                                self.run_timed_sequence(&steps);
+                            }
+                        }
+                        GameCommand::SetConfig(update) => {
+                            self.config.apply(update);
+                            self.log(&format!("Config updated: {:?}", self.config));
+                        }
+                        GameCommand::Stop => {
+                            self.log("Game thread stopping");
+                            return true;
+                        }
                     }
-                    GameCommand::QueueCommand(cmd) => {
-                        self.command_queue.push(cmd);
-                    }
-                    GameCommand::Stop => {
-                        self.log("Game thread stopping");
-                        return true;
-                    }
-                },
+                }
                 Err(mpsc::TryRecvError::Empty) => break,
                 Err(mpsc::TryRecvError::Disconnected) => {
                     self.log("WebSocket thread disconnected, stopping game thread");
@@ -184,25 +1037,71 @@ impl GameThread {
     // ── Phase implementations ──────────────────────────────────────────
 
     fn run_probe(&mut self) {
-        self.log("Probing Game Boy...");
-        match self.exchange(0x29) {
+        self.log(&format!("Probing Game Boy (send 0x{:02X}, expect 0x{:02X})...",
+            self.probe.send, self.probe.expect));
+        match self.exchange(self.probe.send) {
             Ok(response) => {
-                if response == 0x55 {
-                    self.log("Probe OK (0x55)");
+                if response == self.probe.expect {
+                    self.log(&format!("Probe OK (0x{:02X})", response));
                     self.send_event(GameEvent::Connected);
+                    self.probe_misses.seen.clear();
                     self.phase = Phase::MusicSelect;
                 } else {
                     self.log(&format!("Probe unexpected: 0x{:02X}, retrying...", response));
-                    thread::sleep(Duration::from_millis(500));
+                    self.note_probe_miss(Some(response));
+                    self.next_probe_at = self.clock.now() + self.config.probe_retry;
                 }
             }
             Err(e) => {
                 self.log(&format!("Probe failed: {}", e));
-                thread::sleep(Duration::from_millis(1000));
+                self.note_probe_miss(None);
+                self.next_probe_at = self.clock.now() + self.config.probe_error_retry;
             }
         }
     }
 
+    /// Note changes in the emulation speed BGB reports in its status
+    /// packets. Only `tick_interval` acts on it, and only when
+    /// `follow_bgb_speed` is set; the user's `Speed` is never touched.
+    fn follow_reported_speed(&mut self) {
+        let quarters = self.link_stats.reported_speed.load(Ordering::Relaxed);
+        if quarters == self.reported_speed {
+            return;
+        }
+        self.reported_speed = quarters;
+        if !self.config.follow_bgb_speed {
+            return;
+        }
+        match quarters {
+            0 => self.log(&format!("BGB stopped reporting its speed, back to {:.2}x", self.speed.get())),
+            _ => self.log(&format!("BGB reports emulation speed {:.2}x, following it", quarters as f32 / 4.0)),
+        }
+    }
+
+    /// In-game tick interval: scaled by BGB's reported speed while following
+    /// it, otherwise by the user's speed multiplier.
+    fn tick_interval(&self) -> Duration {
+        match self.reported_speed {
+            quarters @ 1.. if self.config.follow_bgb_speed => {
+                TICK_INTERVAL.div_f32((quarters as f32 / 4.0).clamp(Speed::MIN, Speed::MAX))
+            }
+            _ => self.speed.scale_interval(TICK_INTERVAL),
+        }
+    }
+
+    /// Tally a failed probe and report the tally every
+    /// `probe_diagnostic_after` failures.
+    fn note_probe_miss(&mut self, response: Option<u8>) {
+        self.probe_misses.record(response);
+        let after = self.config.probe_diagnostic_after;
+        if after > 0 && self.probe_misses.total() >= after {
+            self.log(&format!("Probe diagnostic: {}", self.probe_misses));
+            let report = self.probe_misses.clone();
+            self.probe_misses.seen.clear();
+            self.send_event(GameEvent::ProbeDiagnostic(report));
+        }
+    }
+
     fn run_music_exchange(&mut self) {
         // Send the music byte and ignore the response (like the music timer)
         let _ = self.exchange(self.music_byte);
@@ -211,7 +1110,9 @@ impl GameThread {
     fn run_game_start_sequence(&mut self, garbage: &[u8], tiles: &[u8], is_first: bool) {
         self.phase = Phase::GameStarting;
         self.command_queue.clear();
-        self.opponent_height = 0;
+        self.opponent_height = self.seeded_height.take().unwrap_or(0);
+        self.sent_queued_last = false;
+        self.last_sent_height = None;
         self.game_ended = false;
 
         if is_first {
@@ -232,20 +1133,18 @@ impl GameThread {
             self.exchange_n(0x29, 70);
         }
 
+        let timing = self.config.start_timing;
+
         // Step 3: send initial garbage
         self.log(&format!("Sending {} garbage bytes", garbage.len()));
-        for &g in garbage {
-            self.exchange_n(g, 4);
-        }
+        self.send_paced(garbage, timing.garbage_delay_ms, timing.burst);
 
         // Step 4: send master again
         self.exchange_n(0x29, 8);
 
         // Step 5: send tiles
         self.log(&format!("Sending {} tile bytes", tiles.len()));
-        for &t in tiles {
-            self.exchange_n(t, 4);
-        }
+        self.send_paced(tiles, timing.tile_delay_ms, timing.burst);
 
         // Step 6: and go
         self.exchange_n(0x30, 70);
@@ -255,31 +1154,217 @@ impl GameThread {
         self.exchange_n(0x20, 70);
 
         self.log("Game start sequence complete, entering game loop");
-        self.game_started_at = Some(std::time::Instant::now());
+        self.game_started_at = Some(self.clock.now());
         self.phase = Phase::InGame;
+        self.send_event(GameEvent::GameStarted);
+    }
+
+    /// Measure the period since the previous in-game tick, reporting the
+    /// mean and jitter every `TIMING_REPORT_TICKS` ticks.
+    fn record_tick(&mut self, now: Instant) {
+        if let Some(previous) = self.last_tick_at.replace(now) {
+            self.tick_periods.push(now.duration_since(previous));
+        }
+        if self.tick_periods.len() < TIMING_REPORT_TICKS {
+            return;
+        }
+        let ms: Vec<f64> = self.tick_periods.drain(..).map(|d| d.as_secs_f64() * 1000.0).collect();
+        let mean = ms.iter().sum::<f64>() / ms.len() as f64;
+        let variance = ms.iter().map(|m| (m - mean).powi(2)).sum::<f64>() / ms.len() as f64;
+        let target_ms = self.tick_interval().as_secs_f64() * 1000.0;
+        let jitter_ms = variance.sqrt();
+        self.vlog(&format!("Tick period {:.1} ms (target {:.1} ms, jitter {:.1} ms)", mean, target_ms, jitter_ms));
+        self.send_event(GameEvent::Timing { target_ms, actual_ms: mean, jitter_ms });
+    }
+
+    /// Warn (rate limited) when exchanges keep taking longer than a tick,
+    /// which means the match can't keep pace.
+    fn check_link_latency(&mut self) {
+        let latency_ms = self.link_stats.last_latency_ms.load(Ordering::Relaxed);
+        let tick_ms = self.tick_interval().as_millis() as u64;
+        if latency_ms <= tick_ms {
+            self.slow_ticks = 0;
+            return;
+        }
+        self.slow_ticks += 1;
+        if self.slow_ticks < SLOW_LINK_TICKS {
+            return;
+        }
+        let now = self.clock.now();
+        if self.last_slow_warning.is_some_and(|at| now.duration_since(at) < SLOW_LINK_REPEAT) {
+            return;
+        }
+        self.last_slow_warning = Some(now);
+        self.log(&format!("Exchanges take {} ms against a {} ms tick; raise BGB's emulation speed",
+            latency_ms, tick_ms));
+        self.send_event(GameEvent::SlowLink { latency_ms, tick_ms });
+    }
+
+    fn force_in_game(&mut self) {
+        if !self.verbose.enabled(Verbosity::GAME) {
+            let msg = "force_in_game is a debug shortcut; enable game verbose logging first".to_string();
+            self.log(&msg);
+            self.send_event(GameEvent::Error(msg));
+            return;
+        }
+        let msg = "force_in_game: skipping probe, music and start sequence (debug shortcut)".to_string();
+        self.log(&msg);
+        self.send_event(GameEvent::Warning(msg));
+        self.opponent_height = self.seeded_height.take().unwrap_or(0);
+        self.sent_queued_last = false;
+        self.last_sent_height = None;
+        self.game_ended = false;
+        self.game_started_at = Some(self.clock.now());
+        self.phase = Phase::InGame;
+        self.send_event(GameEvent::GameStarted);
+    }
+
+    fn snapshot_state(&self) -> String {
+        let snapshot = StateSnapshot {
+            phase: self.phase.name().to_string(),
+            game: self.game.clone(),
+            music: self.music_byte,
+            height: self.opponent_height,
+            queue: self.command_queue.clone(),
+        };
+        serde_json::to_string(&snapshot).expect("snapshot serializes")
+    }
+
+    /// Apply a `snapshot_state` result. Refused, leaving the state alone, if
+    /// the snapshot couldn't have come from a consistent game thread.
+    fn restore_state(&mut self, json: &str) -> Result<(), String> {
+        let snapshot: StateSnapshot = serde_json::from_str(json).map_err(|e| format!("invalid snapshot: {}", e))?;
+        let phase = Phase::from_name(&snapshot.phase).ok_or_else(|| format!("unknown phase {}", snapshot.phase))?;
+        if phase == Phase::GameStarting {
+            return Err("can't resume part way through a start sequence".into());
+        }
+        if phase != Phase::WaitingForGame && snapshot.game.is_empty() {
+            return Err(format!("phase {} needs a game", snapshot.phase));
+        }
+        if snapshot.queue.len() > self.config.queue_limit {
+            return Err(format!("queue longer than the limit of {}", self.config.queue_limit));
+        }
+
+        if let Some(profile) = profile(&snapshot.game) {
+            self.bgb.set_idle_reply(profile.idle_reply);
+        }
+        self.game = snapshot.game;
+        self.music_byte = snapshot.music;
+        self.opponent_height = snapshot.height;
+        self.command_queue = snapshot.queue;
+        self.game_ended = false;
+        self.unrecognized_run = 0;
+        self.next_probe_at = self.clock.now();
+        self.game_started_at = (phase == Phase::InGame).then(|| self.clock.now());
+        self.phase = phase;
+        Ok(())
+    }
+
+    /// Replace the link with a fresh connection, keeping the browser's game
+    /// settings. The old link is shut down first, since BGB takes one
+    /// connection at a time; if the new one fails the browser can retry.
+    fn reconnect_bgb(&mut self) {
+        let Some(reconnect) = &self.reconnect else {
+            self.send_event(GameEvent::Error("reconnect_bgb is not available on this connection".into()));
+            return;
+        };
+        self.log("Reconnecting to BGB");
+        let old = std::mem::replace(&mut self.bgb, Box::new(ClosedLink::default()));
+        if !old.shutdown() {
+            self.log("Old BGB thread did not exit cleanly");
+        }
+        self.send_event(GameEvent::BgbDisconnected);
+
+        match reconnect() {
+            Ok((link, version)) => {
+                self.bgb = link;
+                self.link_stats = self.bgb.stats();
+                self.reported_speed = 0;
+                if let Some(profile) = profile(&self.game) {
+                    self.bgb.set_idle_reply(profile.idle_reply);
+                }
+                self.bgb.set_probe(self.probe);
+                self.send_event(GameEvent::BgbConnected { version, stats: self.link_stats.clone() });
+            }
+            Err(e) => {
+                let msg = format!("reconnect_bgb failed: {}", e);
+                self.log(&msg);
+                self.send_event(GameEvent::Error(msg));
+            }
+        }
+
+        // Whatever was under way was lost with the old connection
+        if matches!(self.phase, Phase::GameStarting | Phase::InGame) {
+            self.send_event(GameEvent::MatchEnded);
+        }
+        self.command_queue.clear();
+        self.game_started_at = None;
+        self.game_ended = false;
+        self.unrecognized_run = 0;
+        if self.game.is_empty() {
+            self.phase = Phase::WaitingForGame;
+        } else {
+            self.phase = Phase::Probing;
+            self.next_probe_at = self.clock.now();
+        }
+    }
+
+    /// Play a browser-scripted sequence. Each delay is clamped to
+    /// `MAX_STEP_DELAY_MS`, and the whole script to `MAX_TIMED_SEQUENCE_MS`.
+    fn run_timed_sequence(&mut self, steps: &[(u8, u64)]) {
+        if steps.len() > MAX_SEQUENCE_LEN {
+            self.send_event(GameEvent::Error(format!("timed_seq longer than {} steps", MAX_SEQUENCE_LEN)));
+            return;
+        }
+        let total_ms: u64 = steps.iter().map(|&(_, delay)| delay.min(MAX_STEP_DELAY_MS)).sum();
+        if total_ms > MAX_TIMED_SEQUENCE_MS {
+            self.send_event(GameEvent::Error(format!(
+                "timed_seq would take {} ms, limit is {} ms", total_ms, MAX_TIMED_SEQUENCE_MS)));
+            return;
+        }
+        self.vlog(&format!("Playing {} step timed sequence ({} ms)", steps.len(), total_ms));
+        for (i, &(byte, delay)) in steps.iter().enumerate() {
+            let _ = self.exchange(byte);
+            self.send_event(GameEvent::TimedSequenceProgress { sent: i + 1, total: steps.len() });
+            let delay = delay.min(MAX_STEP_DELAY_MS);
+            if delay > 0 {
+                self.clock.sleep(Duration::from_millis(delay));
+            }
+        }
+        self.send_event(GameEvent::TimedSequenceDone { steps: steps.len() });
     }
 
     fn run_game_loop_tick(&mut self) {
+        if let Some(byte) = self.loop_override {
+            // Diagnostic mode: the queue and height wait until the override is
+            // cleared, but responses are still reported as usual
+            match self.game_exchange(byte) {
+                Ok(value) => self.interpret_game_byte(value),
+                Err(e) => self.log(&format!("Game loop exchange error: {}", e)),
+            }
+            return;
+        }
+
         if self.game_ended {
             // After win/lose, only drain queued commands (one per tick), don't
             // send height or interpret responses. Matches WebUSB behaviour where
             // gameLoopActive = false stops the loop but queued bytes still send.
             if !self.command_queue.is_empty() {
                 let cmd = self.command_queue.remove(0);
-                let _ = self.exchange(cmd);
+                if self.game_exchange(cmd).is_ok() {
+                    self.send_event(GameEvent::CommandSent(cmd));
+                }
             }
             return;
         }
 
-        // Determine what byte to send (priority: queued commands > opponent height)
-        let byte_to_send = if !self.command_queue.is_empty() {
-            self.command_queue.remove(0)
-        } else {
-            self.opponent_height
-        };
+        let byte_to_send = self.next_game_byte();
 
-        match self.exchange(byte_to_send) {
+        match self.game_exchange(byte_to_send) {
             Ok(value) => {
+                if self.sent_queued_last {
+                    self.send_event(GameEvent::CommandSent(byte_to_send));
+                }
                 self.interpret_game_byte(value);
             }
             Err(e) => {
@@ -288,13 +1373,62 @@ impl GameThread {
         }
     }
 
+    /// In-game exchange, echoed to the browser as `RawByte` when enabled.
+    fn game_exchange(&mut self, byte: u8) -> Result<u8, String> {
+        let received = self.exchange(byte)?;
+        if self.config.echo_raw_bytes {
+            if self.clock.since(self.raw_window_start) >= Duration::from_secs(1) {
+                if self.raw_dropped > 0 {
+                    self.log(&format!("Dropped {} raw_byte events over the rate limit", self.raw_dropped));
+                }
+                self.raw_window_start = self.clock.now();
+                self.raw_in_window = 0;
+                self.raw_dropped = 0;
+            }
+            if self.raw_in_window < RAW_BYTE_RATE_LIMIT {
+                self.raw_in_window += 1;
+                self.send_event(GameEvent::RawByte { sent: byte, received });
+            } else {
+                self.raw_dropped += 1;
+            }
+        }
+        Ok(received)
+    }
+
+    /// Pick this tick's byte: the next queued command or the opponent height,
+    /// according to `queue_policy`.
+    fn next_game_byte(&mut self) -> u8 {
+        let send_queued = !self.command_queue.is_empty() && match self.queue_policy {
+            QueuePolicy::Strict => true,
+            QueuePolicy::Interleaved => !self.sent_queued_last,
+            QueuePolicy::HeightFirst => self.last_sent_height == Some(self.opponent_height),
+        };
+        self.sent_queued_last = send_queued;
+        if send_queued {
+            self.command_queue.remove(0)
+        } else {
+            self.last_sent_height = Some(self.opponent_height);
+            self.opponent_height
+        }
+    }
+
     fn interpret_game_byte(&mut self, value: u8) {
+        let lines = self.line_map.lines_for(value);
+        if !is_reserved_game_byte(value) && lines.is_none() {
+            // Anything else, e.g. 0x14..=0x76 or 0x86..=0xA9 with the default map
+            self.note_unrecognized(value);
+            return;
+        }
+        self.unrecognized_run = 0;
+
         if value < 20 {
             // Height value
             self.send_event(GameEvent::Height(value));
-        } else if value >= 0x80 && value <= 0x85 {
+        } else if let Some(count) = lines {
             // Lines sent
-            self.send_event(GameEvent::Lines(value));
+            self.send_event(GameEvent::Lines { value, count });
+        } else if (value == 0x77 || value == 0xAA) && self.game_ended {
+            // Match already ended — don't report the result twice
         } else if value == 0x77 {
             // We won by reaching 30 lines
             self.log("Game Boy reports WIN (0x77)");
@@ -304,7 +1438,7 @@ impl GameThread {
             // We lost (topped out)
             // Ignore topped-out signal in first 3 seconds
             if let Some(started) = self.game_started_at {
-                if started.elapsed().as_millis() < 3000 {
+                if self.clock.since(started).as_millis() < 3000 {
                     self.log("Ignoring topped out - game just started");
                     return;
                 }
@@ -313,26 +1447,137 @@ impl GameThread {
             self.send_event(GameEvent::Lose);
             self.game_ended = true;
         } else if value == 0xFF {
-            // Screen filled after loss — let the web callback handle queuing 0x43
+            // Screen filled after loss. Unless configured otherwise the browser
+            // decides when to answer with the final-screen byte.
             self.send_event(GameEvent::ScreenFilled);
+            if self.config.auto_final_screen {
+                self.enqueue(FINAL_SCREEN);
+            }
+        }
+    }
+
+    /// Track runs of bytes that match no known signal and report a desync
+    /// once the run reaches the configured threshold. The first byte of a
+    /// run is always logged; the rest only with verbose game logging.
+    fn note_unrecognized(&mut self, value: u8) {
+        self.unrecognized_run += 1;
+        let msg = format!("Unrecognized game byte 0x{:02X} ({} in a row)", value, self.unrecognized_run);
+        if self.unrecognized_run == 1 {
+            self.log(&msg);
+        } else {
+            self.vlog(&msg);
+        }
+        if self.config.report_unhandled {
+            self.send_event(GameEvent::Unhandled(value));
+        }
+        let threshold = self.config.desync_threshold;
+        if threshold > 0 && self.unrecognized_run == threshold {
+            self.log(&format!("Link desync: {} unrecognized bytes in a row", threshold));
+            self.send_event(GameEvent::Desync { count: threshold });
         }
     }
 
     // ── Helpers ────────────────────────────────────────────────────────
 
+    /// True once we've sat idle in a waiting phase for longer than the idle timeout.
+    fn idle_timed_out(&self) -> bool {
+        let Some(timeout) = self.config.idle_timeout else { return false };
+        matches!(self.phase, Phase::WaitingForGame | Phase::WaitingForStart)
+            && self.clock.since(self.last_activity) >= timeout
+    }
+
+    /// Abandon a match that has outlasted `max_session`.
+    fn end_overlong_match(&mut self) {
+        let Some(limit) = self.config.max_session else { return };
+        let Some(started) = self.game_started_at else { return };
+        if self.phase != Phase::InGame || self.clock.since(started) < limit {
+            return;
+        }
+        self.log(&format!("Match ran past the {}s limit, ending it", limit.as_secs()));
+        self.send_event(GameEvent::SessionTimeout { limit });
+        self.command_queue.clear();
+        self.game_started_at = None;
+        self.game_ended = false;
+        self.last_activity = self.clock.now();
+        self.phase = Phase::WaitingForGame;
+        // Free a lazily connected BGB, as end_match would
+        self.bgb.release();
+    }
+
+    /// Report `cmd` as rejected unless `valid`. Returns `valid`.
+    fn check_phase(&self, cmd: &str, valid: bool) -> bool {
+        if !valid {
+            let msg = format!("command {} not valid in phase {}", cmd, self.phase.name());
+            self.log(&msg);
+            self.send_event(GameEvent::Error(msg));
+        }
+        valid
+    }
+
+    /// Queue a byte for the game loop, rejecting it if the queue is full.
+    fn enqueue(&mut self, byte: u8) {
+        if self.command_queue.len() >= self.config.queue_limit {
+            let msg = format!("Command queue full ({}), dropped 0x{:02X}", self.config.queue_limit, byte);
+            self.log(&msg);
+            self.send_event(GameEvent::Warning(msg));
+            return;
+        }
+        self.command_queue.push(byte);
+    }
+
+    /// Emit a heartbeat if the configured interval has elapsed.
+    fn maybe_send_heartbeat(&mut self) {
+        let Some(interval) = self.config.heartbeat_interval else { return };
+        if self.clock.since(self.last_heartbeat) < interval {
+            return;
+        }
+        self.last_heartbeat = self.clock.now();
+        self.send_event(GameEvent::Heartbeat {
+            phase: self.phase.name(),
+            uptime_ms: self.clock.since(self.started_at).as_millis() as u64,
+        });
+    }
+
     /// Exchange one byte with BGB via the link cable.
     fn exchange(&self, byte: u8) -> Result<u8, String> {
         self.bgb.exchange_byte(byte)
     }
 
-    /// Exchange one byte, then sleep for `delay_ms`. Used for timed sequences.
+    /// Exchange one byte, then sleep for `delay_ms`. Used for the start
+    /// sequence, whose delays count from the Game Boy's reply.
     fn exchange_n(&self, byte: u8, delay_ms: u64) {
         let _ = self.exchange(byte);
         if delay_ms > 0 {
-            thread::sleep(Duration::from_millis(delay_ms));
+            self.clock.sleep(Duration::from_millis(delay_ms));
         }
     }
 
+    /// Exchange `bytes` `burst` at a time with `delay_ms` of sleep after
+    /// each burst.
+    fn send_paced(&self, bytes: &[u8], delay_ms: u64, burst: usize) {
+        for chunk in bytes.chunks(burst.max(1)) {
+            for &byte in chunk {
+                let _ = self.exchange(byte);
+            }
+            if delay_ms > 0 {
+                self.clock.sleep(Duration::from_millis(delay_ms));
+            }
+        }
+    }
+
+    /// Exchange every byte of `bytes`, sleeping `delay_ms` between them, and
+    /// collect the responses. Stops at the first failed exchange.
+    fn exchange_seq(&self, bytes: &[u8], delay_ms: u64) -> Result<Vec<u8>, String> {
+        let mut responses = Vec::with_capacity(bytes.len());
+        for &byte in bytes {
+            responses.push(self.exchange(byte)?);
+            if delay_ms > 0 {
+                self.clock.sleep(Duration::from_millis(delay_ms));
+            }
+        }
+        Ok(responses)
+    }
+
     fn send_event(&self, event: GameEvent) {
         let _ = self.event_tx.send(event);
     }
@@ -340,4 +1585,553 @@ impl GameThread {
     fn log(&self, msg: &str) {
         let _ = self.event_tx.send(GameEvent::Log(msg.to_string()));
     }
+
+    fn vlog(&self, msg: &str) {
+        if self.verbose.enabled(Verbosity::GAME) {
+            self.log(msg);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{MockClock, SystemClock};
+    use crate::fake_bgb::FakeBgb;
+
+    fn game_thread() -> (GameThread, mpsc::Sender<GameCommand>, mpsc::Receiver<GameEvent>) {
+        let (cmd_tx, cmd_rx) = mpsc::channel();
+        let (event_tx, event_rx) = mpsc::channel();
+        let game = GameThread::new(Box::new(FakeBgb::start(Vec::new()).connect()), cmd_rx, event_tx, GameConfig::default(),
+            Speed::default(), Verbosity::default(), Box::new(SystemClock));
+        (game, cmd_tx, event_rx)
+    }
+
+    /// A game thread on a silent link whose timers run on a mock clock.
+    fn mock_clock_game_thread(config: GameConfig) -> (GameThread, mpsc::Receiver<GameEvent>, MockClock) {
+        let (_cmd_tx, cmd_rx) = mpsc::channel();
+        let (event_tx, event_rx) = mpsc::channel();
+        let clock = MockClock::new();
+        let game = GameThread::new(Box::new(FailingLink), cmd_rx, event_tx, config,
+            Speed::default(), Verbosity::default(), Box::new(clock.clone()));
+        (game, event_rx, clock)
+    }
+
+    #[test]
+    fn topped_out_is_ignored_for_three_seconds() {
+        let (mut game, event_rx, clock) = mock_clock_game_thread(GameConfig::default());
+        game.phase = Phase::InGame;
+        game.game_started_at = Some(clock.now());
+
+        clock.advance(Duration::from_millis(2999));
+        game.interpret_game_byte(0xAA);
+        assert!(!event_rx.try_iter().any(|e| matches!(e, GameEvent::Lose)));
+
+        clock.advance(Duration::from_millis(1));
+        game.interpret_game_byte(0xAA);
+        assert!(event_rx.try_iter().any(|e| matches!(e, GameEvent::Lose)));
+    }
+
+    #[test]
+    fn heartbeat_waits_for_its_interval() {
+        let config = GameConfig { heartbeat_interval: Some(Duration::from_secs(2)), ..Default::default() };
+        let (mut game, event_rx, clock) = mock_clock_game_thread(config);
+        let heartbeats = |rx: &mpsc::Receiver<GameEvent>| {
+            rx.try_iter().filter(|e| matches!(e, GameEvent::Heartbeat { .. })).count()
+        };
+
+        clock.advance(Duration::from_millis(1999));
+        game.maybe_send_heartbeat();
+        assert_eq!(heartbeats(&event_rx), 0);
+
+        clock.advance(Duration::from_millis(1));
+        game.maybe_send_heartbeat();
+        game.maybe_send_heartbeat();
+        assert_eq!(heartbeats(&event_rx), 1);
+    }
+
+    #[test]
+    fn idle_timeout_only_fires_in_waiting_phases() {
+        let config = GameConfig { idle_timeout: Some(Duration::from_secs(60)), ..Default::default() };
+        let (mut game, _event_rx, clock) = mock_clock_game_thread(config);
+
+        clock.advance(Duration::from_secs(59));
+        assert!(!game.idle_timed_out());
+        clock.advance(Duration::from_secs(1));
+        assert!(game.idle_timed_out());
+
+        game.phase = Phase::MusicSelect;
+        assert!(!game.idle_timed_out());
+    }
+
+    #[test]
+    fn overlong_match_is_ended() {
+        let config = GameConfig { max_session: Some(Duration::from_secs(600)), ..Default::default() };
+        let (mut game, event_rx, clock) = mock_clock_game_thread(config);
+        game.phase = Phase::InGame;
+        game.game_started_at = Some(clock.now());
+
+        clock.advance(Duration::from_secs(599));
+        game.end_overlong_match();
+        assert_eq!(game.phase, Phase::InGame);
+
+        clock.advance(Duration::from_secs(1));
+        game.end_overlong_match();
+        assert_eq!(game.phase, Phase::WaitingForGame);
+        assert!(event_rx.try_iter().any(|e| matches!(e, GameEvent::SessionTimeout { .. })));
+    }
+
+    #[test]
+    fn overlong_match_releases_a_lazy_link() {
+        let (link, released) = lazy_fake_link();
+        let (_cmd_tx, cmd_rx) = mpsc::channel();
+        let (event_tx, _event_rx) = mpsc::channel();
+        let clock = MockClock::new();
+        let config = GameConfig { max_session: Some(Duration::from_secs(600)), ..Default::default() };
+        let mut game = GameThread::new(Box::new(link), cmd_rx, event_tx, config,
+            Speed::default(), Verbosity::default(), Box::new(clock.clone()));
+        game.phase = Phase::InGame;
+        game.game_started_at = Some(clock.now());
+        game.exchange(0x00).unwrap();
+
+        clock.advance(Duration::from_secs(600));
+        game.end_overlong_match();
+        assert!(released.load(Ordering::Relaxed));
+        assert!(!game.link_stats.connected.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn tick_timing_reports_mean_period_and_jitter() {
+        let (mut game, event_rx, clock) = mock_clock_game_thread(GameConfig::default());
+        game.record_tick(clock.now());
+        for i in 0..TIMING_REPORT_TICKS {
+            clock.advance(Duration::from_millis(if i % 2 == 0 { 90 } else { 110 }));
+            game.record_tick(clock.now());
+        }
+        let timings: Vec<(f64, f64, f64)> = event_rx.try_iter()
+            .filter_map(|e| match e {
+                GameEvent::Timing { target_ms, actual_ms, jitter_ms } => Some((target_ms, actual_ms, jitter_ms)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(timings.len(), 1);
+        let (target, actual, jitter) = timings[0];
+        assert!((target - 100.0).abs() < 1e-3);
+        assert!((actual - 100.0).abs() < 1e-3);
+        assert!((jitter - 10.0).abs() < 1e-3);
+        assert!(game.tick_periods.is_empty());
+    }
+
+    #[test]
+    fn slow_link_is_reported_after_a_run_of_slow_ticks_and_rate_limited() {
+        let (mut game, event_rx, clock) = mock_clock_game_thread(GameConfig::default());
+        let slow_links = |rx: &mpsc::Receiver<GameEvent>| {
+            rx.try_iter().filter(|e| matches!(e, GameEvent::SlowLink { .. })).count()
+        };
+        game.link_stats.last_latency_ms.store(250, Ordering::Relaxed);
+
+        for _ in 0..SLOW_LINK_TICKS - 1 {
+            game.check_link_latency();
+        }
+        assert_eq!(slow_links(&event_rx), 0);
+        game.check_link_latency();
+        assert_eq!(slow_links(&event_rx), 1);
+
+        // Still slow, but within the repeat interval
+        for _ in 0..SLOW_LINK_TICKS {
+            game.check_link_latency();
+        }
+        assert_eq!(slow_links(&event_rx), 0);
+
+        clock.advance(SLOW_LINK_REPEAT);
+        game.check_link_latency();
+        assert_eq!(slow_links(&event_rx), 1);
+
+        // A fast tick restarts the run
+        game.link_stats.last_latency_ms.store(20, Ordering::Relaxed);
+        game.check_link_latency();
+        assert_eq!(game.slow_ticks, 0);
+    }
+
+    #[test]
+    fn start_sequence_time_scales_with_start_timing() {
+        let elapsed = |garbage_delay_ms, tile_delay_ms, burst| {
+            let start_timing = StartTiming { garbage_delay_ms, tile_delay_ms, burst };
+            let (mut game, _event_rx, clock) = mock_clock_game_thread(GameConfig { start_timing, ..Default::default() });
+            let started = clock.now();
+            game.run_game_start_sequence(&[0; 10], &[0; 20], true);
+            clock.now() - started
+        };
+        let fixed = elapsed(0, 0, 1);
+        let ms = Duration::from_millis;
+
+        assert_eq!(elapsed(4, 4, 1) - fixed, ms(10 * 4 + 20 * 4));
+        assert_eq!(elapsed(10, 2, 1) - fixed, ms(10 * 10 + 20 * 2));
+        // Bursts of 5: two garbage sleeps and four tile sleeps
+        assert_eq!(elapsed(4, 4, 5) - fixed, ms(2 * 4 + 4 * 4));
+    }
+
+    #[test]
+    fn timed_sequence_clamps_delays_and_reports_progress() {
+        let (mut game, event_rx, clock) = mock_clock_game_thread(GameConfig::default());
+        let start = clock.now();
+
+        game.run_timed_sequence(&[(0x60, 5), (0x02, MAX_STEP_DELAY_MS * 5), (0x03, 0)]);
+        assert_eq!(clock.since(start), Duration::from_millis(5 + MAX_STEP_DELAY_MS));
+        let events: Vec<GameEvent> = event_rx.try_iter().collect();
+        let progress = events.iter().filter(|e| matches!(e, GameEvent::TimedSequenceProgress { total: 3, .. })).count();
+        assert_eq!(progress, 3);
+        assert!(matches!(events.last(), Some(GameEvent::TimedSequenceDone { steps: 3 })));
+
+        // Too long even after clamping: rejected without sending anything
+        let steps = vec![(0x00, MAX_STEP_DELAY_MS); (MAX_TIMED_SEQUENCE_MS / MAX_STEP_DELAY_MS) as usize + 1];
+        let start = clock.now();
+        game.run_timed_sequence(&steps);
+        assert_eq!(clock.since(start), Duration::ZERO);
+        assert!(matches!(event_rx.try_iter().next(), Some(GameEvent::Error(_))));
+    }
+
+    #[test]
+    fn unhandled_bytes_are_reported_when_enabled() {
+        let (mut game, _cmd_tx, event_rx) = game_thread();
+        game.phase = Phase::InGame;
+
+        game.interpret_game_byte(0x86);
+        assert!(!event_rx.try_iter().any(|e| matches!(e, GameEvent::Unhandled(_))));
+
+        game.config.report_unhandled = true;
+        for value in [0x56, 0x78, 0xA9, 0x85] {
+            game.interpret_game_byte(value);
+        }
+        let unhandled: Vec<u8> = event_rx.try_iter()
+            .filter_map(|e| match e { GameEvent::Unhandled(v) => Some(v), _ => None })
+            .collect();
+        assert_eq!(unhandled, [0x56, 0x78, 0xA9]);
+    }
+
+    #[test]
+    fn line_map_decodes_lines_and_encodes_queue_lines() {
+        let (mut game, cmd_tx, event_rx) = game_thread();
+        game.phase = Phase::InGame;
+        game.interpret_game_byte(0x82);
+        assert!(event_rx.try_iter().any(|e| matches!(e, GameEvent::Lines { value: 0x82, count: 2 })));
+
+        let map = LineMap::new(vec![(1, 0x90), (4, 0x93)]).unwrap();
+        cmd_tx.send(GameCommand::SetLineMap(map)).unwrap();
+        cmd_tx.send(GameCommand::QueueLines(4)).unwrap();
+        cmd_tx.send(GameCommand::QueueLines(2)).unwrap();
+        assert!(!game.process_commands());
+        assert_eq!(game.command_queue, [0x93]);
+        assert!(event_rx.try_iter().any(|e| matches!(e, GameEvent::Error(msg) if msg.contains("2 lines"))));
+
+        game.interpret_game_byte(0x90);
+        game.interpret_game_byte(0x82);
+        let events: Vec<_> = event_rx.try_iter().collect();
+        assert!(events.iter().any(|e| matches!(e, GameEvent::Lines { value: 0x90, count: 1 })));
+        assert!(!events.iter().any(|e| matches!(e, GameEvent::Lines { value: 0x82, .. })));
+
+        assert!(LineMap::new(vec![(1, 0x77)]).is_err());
+        assert!(LineMap::new(vec![(1, 0x90), (1, 0x91)]).is_err());
+    }
+
+    #[test]
+    fn repeated_lose_signal_emits_one_event() {
+        let (mut game, event_rx, clock) = mock_clock_game_thread(GameConfig::default());
+        game.phase = Phase::InGame;
+        game.game_started_at = Some(clock.now());
+        clock.advance(Duration::from_secs(4));
+
+        for _ in 0..3 {
+            game.interpret_game_byte(0xAA);
+        }
+
+        let loses = event_rx.try_iter().filter(|e| matches!(e, GameEvent::Lose)).count();
+        assert_eq!(loses, 1);
+    }
+
+    /// Send `cmd` in `phase` and assert it is rejected without changing phase.
+    fn assert_rejected(phase: Phase, cmd: GameCommand) {
+        let (mut game, cmd_tx, event_rx) = game_thread();
+        game.phase = phase.clone();
+        cmd_tx.send(cmd).unwrap();
+
+        assert!(!game.process_commands());
+
+        assert_eq!(game.phase, phase);
+        assert!(event_rx.try_iter().any(|e| matches!(e, GameEvent::Error(_))));
+    }
+
+    fn start_game() -> GameCommand {
+        GameCommand::StartGame { garbage: vec![], tiles: vec![], is_first: true }
+    }
+
+    #[test]
+    fn start_game_rejected_before_music_confirmed() {
+        assert_rejected(Phase::WaitingForGame, start_game());
+        assert_rejected(Phase::Probing, start_game());
+        assert_rejected(Phase::MusicSelect, start_game());
+        assert_rejected(Phase::InGame, start_game());
+    }
+
+    #[test]
+    fn confirm_music_rejected_outside_music_select() {
+        assert_rejected(Phase::WaitingForGame, GameCommand::ConfirmMusic);
+        assert_rejected(Phase::Probing, GameCommand::ConfirmMusic);
+        assert_rejected(Phase::WaitingForStart, GameCommand::ConfirmMusic);
+        assert_rejected(Phase::InGame, GameCommand::ConfirmMusic);
+    }
+
+    #[test]
+    fn set_game_rejected_during_match() {
+        let set_game = || GameCommand::SetGame { game: "tetris".into(), probe: Probe::default() };
+        assert_rejected(Phase::GameStarting, set_game());
+        assert_rejected(Phase::InGame, set_game());
+    }
+
+    #[test]
+    fn selftest_rejected_once_music_is_confirmed() {
+        assert_rejected(Phase::WaitingForStart, GameCommand::SelfTest);
+        assert_rejected(Phase::GameStarting, GameCommand::SelfTest);
+        assert_rejected(Phase::InGame, GameCommand::SelfTest);
+    }
+
+    #[test]
+    fn reported_bgb_speed_paces_ticks_only_when_followed() {
+        let (mut game, _event_rx, _clock) = mock_clock_game_thread(GameConfig::default());
+        game.speed.set(2.0);
+        let user_tick = TICK_INTERVAL.div_f32(2.0);
+
+        game.link_stats.reported_speed.store(8, Ordering::Relaxed);
+        game.follow_reported_speed();
+        assert_eq!(game.tick_interval(), user_tick);
+
+        game.config.follow_bgb_speed = true;
+        game.link_stats.reported_speed.store(2, Ordering::Relaxed);
+        game.follow_reported_speed();
+        assert_eq!(game.tick_interval(), TICK_INTERVAL.div_f32(0.5));
+        assert_eq!(game.speed.get(), 2.0);
+
+        // Once BGB stops reporting, the user's speed applies again
+        game.link_stats.reported_speed.store(0, Ordering::Relaxed);
+        game.follow_reported_speed();
+        assert_eq!(game.tick_interval(), user_tick);
+        assert_eq!(game.speed.get(), 2.0);
+    }
+
+    #[test]
+    fn send_seq_rejected_during_match() {
+        assert_rejected(Phase::GameStarting, GameCommand::SendSequence(vec![0x02]));
+        assert_rejected(Phase::InGame, GameCommand::SendSequence(vec![0x02]));
+    }
+
+    #[test]
+    fn timed_seq_rejected_during_match() {
+        assert_rejected(Phase::GameStarting, GameCommand::TimedSequence(vec![(0x02, 0)]));
+        assert_rejected(Phase::InGame, GameCommand::TimedSequence(vec![(0x02, 0)]));
+    }
+
+    /// A lazy link to a fake BGB, and a flag set once it is released.
+    fn lazy_fake_link() -> (crate::link::LazyLink, Arc<std::sync::atomic::AtomicBool>) {
+        let fake = FakeBgb::start(Vec::new());
+        let released = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let link = {
+            let released = released.clone();
+            crate::link::LazyLink::new(
+                move || Ok(fake.connect()),
+                move |version| released.store(version.is_none(), Ordering::Relaxed),
+            )
+        };
+        (link, released)
+    }
+
+    #[test]
+    fn end_match_releases_a_lazy_link() {
+        let (link, released) = lazy_fake_link();
+        let (cmd_tx, cmd_rx) = mpsc::channel();
+        let (event_tx, _event_rx) = mpsc::channel();
+        let mut game = GameThread::new(Box::new(link), cmd_rx, event_tx, GameConfig::default(),
+            Speed::default(), Verbosity::default(), Box::new(SystemClock));
+        game.phase = Phase::InGame;
+        game.exchange(0x00).unwrap();
+        assert!(game.link_stats.connected.load(Ordering::Relaxed));
+
+        cmd_tx.send(GameCommand::EndMatch).unwrap();
+        assert!(!game.process_commands());
+        assert!(released.load(Ordering::Relaxed));
+        assert!(!game.link_stats.connected.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn end_match_rejected_outside_game() {
+        assert_rejected(Phase::WaitingForGame, GameCommand::EndMatch);
+        assert_rejected(Phase::MusicSelect, GameCommand::EndMatch);
+        assert_rejected(Phase::WaitingForStart, GameCommand::EndMatch);
+    }
+
+    #[test]
+    fn command_queue_is_bounded() {
+        let (mut game, cmd_tx, event_rx) = game_thread();
+        let limit = game.config.queue_limit;
+        for i in 0..limit + 5 {
+            cmd_tx.send(GameCommand::QueueCommand(i as u8)).unwrap();
+        }
+
+        assert!(!game.process_commands());
+
+        assert_eq!(game.command_queue.len(), limit);
+        let warnings = event_rx.try_iter().filter(|e| matches!(e, GameEvent::Warning(_))).count();
+        assert_eq!(warnings, 5);
+    }
+
+    /// Link that records what was sent and always answers 0.
+    struct RecordingLink {
+        sent: Arc<std::sync::Mutex<Vec<u8>>>,
+    }
+
+    impl Link for RecordingLink {
+        fn exchange_byte(&self, send: u8) -> Result<u8, String> {
+            self.sent.lock().unwrap().push(send);
+            Ok(0)
+        }
+
+        fn stats(&self) -> Arc<LinkStats> {
+            Arc::default()
+        }
+
+        fn reset_link(&self) -> Result<usize, String> {
+            Ok(0)
+        }
+    }
+
+    #[test]
+    fn interleaved_policy_alternates_queue_and_height() {
+        let sent = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (_cmd_tx, cmd_rx) = mpsc::channel();
+        let (event_tx, event_rx) = mpsc::channel();
+        let link = RecordingLink { sent: sent.clone() };
+        let mut game = GameThread::new(Box::new(link), cmd_rx, event_tx, GameConfig::default(),
+            Speed::default(), Verbosity::default(), Box::new(SystemClock));
+        game.phase = Phase::InGame;
+        game.queue_policy = QueuePolicy::Interleaved;
+        game.opponent_height = 5;
+        game.command_queue = vec![0x80, 0x81, 0x82];
+
+        for _ in 0..7 {
+            game.run_game_loop_tick();
+        }
+
+        assert_eq!(*sent.lock().unwrap(), vec![0x80, 5, 0x81, 5, 0x82, 5, 5]);
+        let landed: Vec<u8> = event_rx.try_iter()
+            .filter_map(|e| match e { GameEvent::CommandSent(b) => Some(b), _ => None })
+            .collect();
+        assert_eq!(landed, vec![0x80, 0x81, 0x82]);
+    }
+
+    /// Link whose exchanges always fail, like a stalled BGB.
+    struct FailingLink;
+
+    impl Link for FailingLink {
+        fn exchange_byte(&self, _send: u8) -> Result<u8, String> {
+            Err("BGB exchange timeout".into())
+        }
+
+        fn stats(&self) -> Arc<LinkStats> {
+            Arc::default()
+        }
+
+        fn reset_link(&self) -> Result<usize, String> {
+            Ok(0)
+        }
+    }
+
+    #[test]
+    fn failed_confirm_music_stays_in_music_select() {
+        let (cmd_tx, cmd_rx) = mpsc::channel();
+        let (event_tx, event_rx) = mpsc::channel();
+        let mut game = GameThread::new(Box::new(FailingLink), cmd_rx, event_tx, GameConfig::default(),
+            Speed::default(), Verbosity::default(), Box::new(SystemClock));
+        game.phase = Phase::MusicSelect;
+        cmd_tx.send(GameCommand::ConfirmMusic).unwrap();
+
+        assert!(!game.process_commands());
+
+        assert_eq!(game.phase, Phase::MusicSelect);
+        assert!(event_rx.try_iter().any(|e| matches!(e, GameEvent::Error(msg) if msg.contains("confirm_music"))));
+    }
+
+    #[test]
+    fn seeded_height_is_first_sent_in_game() {
+        let sent = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (cmd_tx, cmd_rx) = mpsc::channel();
+        let (event_tx, _event_rx) = mpsc::channel();
+        let link = RecordingLink { sent: sent.clone() };
+        let mut game = GameThread::new(Box::new(link), cmd_rx, event_tx, GameConfig::default(),
+            Speed::default(), Verbosity::default(), Box::new(SystemClock));
+        game.phase = Phase::WaitingForStart;
+        cmd_tx.send(GameCommand::SetHeight(7)).unwrap();
+        assert!(!game.process_commands());
+
+        game.run_game_start_sequence(&[], &[], true);
+        assert_eq!(game.phase, Phase::InGame);
+        sent.lock().unwrap().clear();
+        game.run_game_loop_tick();
+
+        assert_eq!(*sent.lock().unwrap(), vec![7]);
+    }
+
+    #[test]
+    fn force_in_game_needs_game_verbose_logging() {
+        let (mut game, event_rx, _clock) = mock_clock_game_thread(GameConfig::default());
+        game.force_in_game();
+        assert_eq!(game.phase, Phase::WaitingForGame);
+        assert!(event_rx.try_iter().any(|e| matches!(e, GameEvent::Error(msg) if msg.contains("debug shortcut"))));
+
+        game.verbose.set(Verbosity::GAME);
+        game.force_in_game();
+        assert_eq!(game.phase, Phase::InGame);
+        assert!(game.game_started_at.is_some());
+        let events: Vec<_> = event_rx.try_iter().collect();
+        assert!(events.iter().any(|e| matches!(e, GameEvent::Warning(_))));
+        assert!(events.iter().any(|e| matches!(e, GameEvent::GameStarted)));
+    }
+
+    #[test]
+    fn restored_snapshot_reproduces_the_state() {
+        let (mut game, _event_rx, _clock) = mock_clock_game_thread(GameConfig::default());
+        game.game = "tetris".into();
+        game.phase = Phase::WaitingForStart;
+        game.music_byte = 0x1D;
+        game.opponent_height = 4;
+        game.command_queue = vec![0x43, 0x27];
+        let snapshot = game.snapshot_state();
+
+        let (mut restored, _event_rx, _clock) = mock_clock_game_thread(GameConfig::default());
+        restored.restore_state(&snapshot).unwrap();
+        assert_eq!(restored.phase, Phase::WaitingForStart);
+        assert_eq!(restored.snapshot_state(), snapshot);
+
+        let in_game_without_game = r#"{"phase":"in_game","game":"","music":0,"height":0,"queue":[]}"#;
+        assert_eq!(restored.restore_state(in_game_without_game).unwrap_err(), "phase in_game needs a game");
+        assert!(restored.restore_state(&snapshot.replace("waiting_for_start", "game_starting")).is_err());
+        assert!(restored.restore_state(&snapshot.replace("waiting_for_start", "dancing")).is_err());
+        assert_eq!(restored.phase, Phase::WaitingForStart);
+    }
+
+    #[test]
+    fn reconnect_bgb_swaps_the_link_and_restarts_probing() {
+        let (mut game, event_rx, _clock) = mock_clock_game_thread(GameConfig::default());
+        game.game = "tetris".into();
+        game.phase = Phase::InGame;
+        game.set_reconnect(Box::new(|| Ok((Box::new(crate::link::SimLink::new(None)) as Box<dyn Link>, None))));
+
+        game.reconnect_bgb();
+
+        let events: Vec<_> = event_rx.try_iter().collect();
+        let disconnected = events.iter().position(|e| matches!(e, GameEvent::BgbDisconnected)).unwrap();
+        let connected = events.iter().position(|e| matches!(e, GameEvent::BgbConnected { .. })).unwrap();
+        assert!(disconnected < connected);
+        assert!(events.iter().any(|e| matches!(e, GameEvent::MatchEnded)));
+        assert_eq!(game.phase, Phase::Probing);
+        assert_eq!(game.bgb.exchange_byte(0x29), Ok(0x55));
+    }
 }
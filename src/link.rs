@@ -0,0 +1,403 @@
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+
+use crate::bgb::{BgbClient, BgbVersion, Direction, LinkStats, PacketRecord, SerialClock};
+use crate::game::Probe;
+
+/// A Game Boy link the game thread and bridge exchange bytes over.
+/// `BgbClient` is the real transport, `LazyLink` wraps it to connect on
+/// demand, and `SimLink` stands in when no emulator is attached.
+pub trait Link: Send {
+    /// Exchange one byte and return the Game Boy's reply.
+    fn exchange_byte(&self, send: u8) -> Result<u8, String>;
+
+    /// Exchange one byte and return the reply with its control (SC) byte.
+    /// Links without a real cable report 0x80, a plain slave reply.
+    fn exchange_byte_full(&self, send: u8) -> Result<(u8, u8), String> {
+        self.exchange_byte(send).map(|data| (data, 0x80))
+    }
+
+    /// Counters for this link.
+    fn stats(&self) -> Arc<LinkStats>;
+
+    /// Drop any in-flight transfer and queued responses so the next
+    /// exchange starts clean. Returns how many stale responses were dropped.
+    fn reset_link(&self) -> Result<usize, String>;
+
+    /// Choose who drives the serial clock on later transfers. Links without
+    /// a real cable ignore this.
+    fn set_clock(&self, _clock: SerialClock) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Pause or resume the emulator on the other end. Links without an
+    /// emulator ignore this.
+    fn set_paused(&self, _paused: bool) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Byte to answer transfers the emulator starts on its own. Links
+    /// that never see such transfers ignore this.
+    fn set_idle_reply(&self, _byte: u8) {}
+
+    /// The probe the game thread is about to send. Only a simulated Game
+    /// Boy, which has to answer it, does anything here.
+    fn set_probe(&self, _probe: Probe) {}
+
+    /// The last packets exchanged with the emulator, oldest first. Links
+    /// without a packet transport have none.
+    fn recent_packets(&self) -> Vec<PacketRecord> {
+        Vec::new()
+    }
+
+    /// Let go of the emulator until the link is next used. Only links that
+    /// connect on demand do anything here.
+    fn release(&self) {}
+
+    /// Release the link, waiting for any background thread to exit.
+    /// Returns false if it did not exit cleanly.
+    fn shutdown(self: Box<Self>) -> bool {
+        true
+    }
+}
+
+impl Link for BgbClient {
+    fn exchange_byte(&self, send: u8) -> Result<u8, String> {
+        BgbClient::exchange_byte(self, send)
+    }
+
+    fn exchange_byte_full(&self, send: u8) -> Result<(u8, u8), String> {
+        BgbClient::exchange_byte_full(self, send)
+    }
+
+    fn stats(&self) -> Arc<LinkStats> {
+        BgbClient::stats(self)
+    }
+
+    fn reset_link(&self) -> Result<usize, String> {
+        BgbClient::reset_link(self)
+    }
+
+    fn set_clock(&self, clock: SerialClock) -> Result<(), String> {
+        BgbClient::set_clock(self, clock)
+    }
+
+    fn set_paused(&self, paused: bool) -> Result<(), String> {
+        BgbClient::set_paused(self, paused)
+    }
+
+    fn set_idle_reply(&self, byte: u8) {
+        BgbClient::set_idle_reply(self, byte)
+    }
+
+    fn recent_packets(&self) -> Vec<PacketRecord> {
+        BgbClient::dump_recent_packets(self)
+    }
+
+    fn shutdown(self: Box<Self>) -> bool {
+        BgbClient::shutdown(*self)
+    }
+}
+
+/// Stands in while a link is being replaced: every exchange fails.
+#[derive(Default)]
+pub struct ClosedLink {
+    stats: Arc<LinkStats>,
+}
+
+impl Link for ClosedLink {
+    fn exchange_byte(&self, _send: u8) -> Result<u8, String> {
+        Err("BGB not connected".into())
+    }
+
+    fn stats(&self) -> Arc<LinkStats> {
+        self.stats.clone()
+    }
+
+    fn reset_link(&self) -> Result<usize, String> {
+        Ok(0)
+    }
+}
+
+/// Heights the simulated Game Boy reports in turn during a match.
+const SIM_HEIGHTS: [u8; 12] = [0, 0, 1, 1, 2, 3, 3, 4, 5, 4, 3, 2];
+
+/// Simulated link for front-end development without BGB. Logs every
+/// exchange and answers with canned bytes: the probe gets its expected reply
+/// so the state machine advances, and everything else gets a scripted height.
+pub struct SimLink {
+    log_tx: Option<mpsc::Sender<String>>,
+    stats: Arc<LinkStats>,
+    probe: Mutex<Probe>,
+    next_height: Mutex<usize>,
+}
+
+impl SimLink {
+    pub fn new(log_tx: Option<mpsc::Sender<String>>) -> Self {
+        let stats = Arc::new(LinkStats::default());
+        stats.connected.store(true, Ordering::Relaxed);
+        Self { log_tx, stats, probe: Mutex::default(), next_height: Mutex::new(0) }
+    }
+}
+
+impl Link for SimLink {
+    fn exchange_byte(&self, send: u8) -> Result<u8, String> {
+        let probe = *self.probe.lock().unwrap();
+        let reply = if send == probe.send {
+            probe.expect
+        } else {
+            let mut next = self.next_height.lock().unwrap();
+            let height = SIM_HEIGHTS[*next];
+            *next = (*next + 1) % SIM_HEIGHTS.len();
+            height
+        };
+        self.stats.exchanges.fetch_add(1, Ordering::Relaxed);
+        self.stats.trace_byte(Direction::Sent, send);
+        self.stats.trace_byte(Direction::Received, reply);
+        if let Some(ref tx) = self.log_tx {
+            let _ = tx.send(format!("[SIM] send 0x{:02X} -> reply 0x{:02X}", send, reply));
+        }
+        Ok(reply)
+    }
+
+    fn stats(&self) -> Arc<LinkStats> {
+        self.stats.clone()
+    }
+
+    fn reset_link(&self) -> Result<usize, String> {
+        // Nothing is ever in flight; restart the scripted heights
+        *self.next_height.lock().unwrap() = 0;
+        Ok(0)
+    }
+
+    fn set_probe(&self, probe: Probe) {
+        *self.probe.lock().unwrap() = probe;
+    }
+}
+
+/// Connects to BGB on first use and disconnects on `release`, so a BGB
+/// shared with other tools is only held while a game is being played.
+/// Keeps its own counters, which outlive each connection.
+pub struct LazyLink {
+    connect: Box<dyn Fn() -> Result<BgbClient, String> + Send>,
+    /// Told the BGB version on connect and None on disconnect
+    notify: Box<dyn Fn(Option<BgbVersion>) + Send>,
+    state: Mutex<LazyState>,
+    stats: Arc<LinkStats>,
+}
+
+/// The current connection, plus settings to apply to the next one.
+#[derive(Default)]
+struct LazyState {
+    client: Option<BgbClient>,
+    idle_reply: Option<u8>,
+    clock: Option<SerialClock>,
+}
+
+impl LazyLink {
+    pub fn new(
+        connect: impl Fn() -> Result<BgbClient, String> + Send + 'static,
+        notify: impl Fn(Option<BgbVersion>) + Send + 'static,
+    ) -> Self {
+        Self {
+            connect: Box::new(connect),
+            notify: Box::new(notify),
+            state: Mutex::default(),
+            stats: Arc::default(),
+        }
+    }
+
+    /// Run `f` on the current client, connecting first if there is none.
+    /// A client whose thread has died is dropped so the next call reconnects.
+    fn with_client<T>(&self, f: impl FnOnce(&BgbClient) -> Result<T, String>) -> Result<T, String> {
+        let mut state = self.state.lock().unwrap();
+        if state.client.is_none() {
+            let client = (self.connect)()?;
+            if let Some(byte) = state.idle_reply {
+                client.set_idle_reply(byte);
+            }
+            if let Some(clock) = state.clock {
+                client.set_clock(clock)?;
+            }
+            self.stats.connected.store(true, Ordering::Relaxed);
+            (self.notify)(Some(client.version()));
+            state.client = Some(client);
+        }
+        let client = state.client.as_ref().unwrap();
+        let result = f(client);
+        let client_stats = client.stats();
+        self.stats.last_latency_ms.store(client_stats.last_latency_ms.load(Ordering::Relaxed), Ordering::Relaxed);
+        self.stats.reported_speed.store(client_stats.reported_speed.load(Ordering::Relaxed), Ordering::Relaxed);
+        for (ours, theirs) in self.stats.latency_histogram.iter().zip(&client_stats.latency_histogram) {
+            ours.store(theirs.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+        for (direction, byte) in client_stats.take_byte_trace() {
+            self.stats.trace_byte(direction, byte);
+        }
+        if !client_stats.connected.load(Ordering::Relaxed) {
+            Self::disconnect(&mut state, &self.stats, &self.notify);
+        }
+        result
+    }
+
+    fn disconnect(state: &mut LazyState, stats: &LinkStats, notify: &dyn Fn(Option<BgbVersion>)) {
+        if let Some(client) = state.client.take() {
+            client.shutdown();
+            stats.connected.store(false, Ordering::Relaxed);
+            notify(None);
+        }
+    }
+}
+
+impl Link for LazyLink {
+    fn exchange_byte(&self, send: u8) -> Result<u8, String> {
+        self.exchange_byte_full(send).map(|(data, _)| data)
+    }
+
+    fn exchange_byte_full(&self, send: u8) -> Result<(u8, u8), String> {
+        let reply = self.with_client(|c| c.exchange_byte_full(send))?;
+        self.stats.exchanges.fetch_add(1, Ordering::Relaxed);
+        Ok(reply)
+    }
+
+
+    fn stats(&self) -> Arc<LinkStats> {
+        self.stats.clone()
+    }
+
+    fn reset_link(&self) -> Result<usize, String> {
+        match &self.state.lock().unwrap().client {
+            Some(client) => client.reset_link(),
+            None => Ok(0),
+        }
+    }
+
+    fn set_clock(&self, clock: SerialClock) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        state.clock = Some(clock);
+        match &state.client {
+            Some(client) => client.set_clock(clock),
+            None => Ok(()),
+        }
+    }
+
+    fn set_paused(&self, paused: bool) -> Result<(), String> {
+        match &self.state.lock().unwrap().client {
+            Some(client) => client.set_paused(paused),
+            None => Err("BGB not connected".into()),
+        }
+    }
+
+    fn set_idle_reply(&self, byte: u8) {
+        let mut state = self.state.lock().unwrap();
+        state.idle_reply = Some(byte);
+        if let Some(client) = &state.client {
+            client.set_idle_reply(byte);
+        }
+    }
+
+    fn recent_packets(&self) -> Vec<PacketRecord> {
+        match &self.state.lock().unwrap().client {
+            Some(client) => client.dump_recent_packets(),
+            None => Vec::new(),
+        }
+    }
+
+    fn release(&self) {
+        Self::disconnect(&mut self.state.lock().unwrap(), &self.stats, &self.notify);
+    }
+
+    fn shutdown(self: Box<Self>) -> bool {
+        match self.state.into_inner().unwrap().client {
+            Some(client) => client.shutdown(),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_bgb::{FakeBgb, Reply};
+
+    type Shared<T> = Arc<Mutex<Vec<T>>>;
+
+    /// A `LazyLink` onto fresh fake BGBs answering 0x55, one per connect,
+    /// with the versions it reported (None for a disconnect).
+    fn lazy_link() -> (LazyLink, Shared<FakeBgb>, Shared<Option<BgbVersion>>) {
+        let fakes = Arc::new(Mutex::new(Vec::new()));
+        let notified = Arc::new(Mutex::new(Vec::new()));
+        let link = {
+            let fakes = fakes.clone();
+            let notified = notified.clone();
+            LazyLink::new(
+                move || {
+                    let fake = FakeBgb::start(vec![Reply::Sync2(0x55)]);
+                    let client = fake.connect();
+                    fakes.lock().unwrap().push(fake);
+                    Ok(client)
+                },
+                move |version| notified.lock().unwrap().push(version),
+            )
+        };
+        (link, fakes, notified)
+    }
+
+    #[test]
+    fn lazy_link_connects_on_first_use() {
+        let (link, fakes, notified) = lazy_link();
+        link.set_idle_reply(0x02);
+        assert!(fakes.lock().unwrap().is_empty());
+        assert!(!link.stats().connected.load(Ordering::Relaxed));
+
+        assert_eq!(link.exchange_byte(0x29), Ok(0x55));
+        assert_eq!(link.exchange_byte(0x29), Ok(0x00));
+        assert_eq!(fakes.lock().unwrap().len(), 1);
+        assert!(link.stats().connected.load(Ordering::Relaxed));
+        assert_eq!(link.stats().exchanges.load(Ordering::Relaxed), 2);
+        assert!(matches!(notified.lock().unwrap()[..], [Some(_)]));
+    }
+
+    #[test]
+    fn released_lazy_link_reconnects_on_next_use() {
+        let (link, fakes, notified) = lazy_link();
+        link.exchange_byte(0x29).unwrap();
+
+        link.release();
+        assert!(!link.stats().connected.load(Ordering::Relaxed));
+        assert!(matches!(notified.lock().unwrap()[..], [Some(_), None]));
+        // Releasing again has nothing to let go of
+        link.release();
+        assert_eq!(notified.lock().unwrap().len(), 2);
+
+        assert_eq!(link.exchange_byte(0x29), Ok(0x55));
+        assert_eq!(fakes.lock().unwrap().len(), 2);
+        assert!(matches!(notified.lock().unwrap()[..], [Some(_), None, Some(_)]));
+        // Counters outlive each connection
+        assert_eq!(link.stats().exchanges.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn sim_link_answers_the_current_probe() {
+        let link = SimLink::new(None);
+        assert_eq!(link.exchange_byte(0x29), Ok(0x55));
+
+        link.set_probe(Probe { send: 0x60, expect: 0x0F });
+        assert_eq!(link.exchange_byte(0x60), Ok(0x0F));
+        // The old probe byte is now just another transfer
+        assert_eq!(link.exchange_byte(0x29), Ok(SIM_HEIGHTS[0]));
+    }
+
+    #[test]
+    fn sim_link_cycles_the_scripted_heights() {
+        let link = SimLink::new(None);
+        let heights: Vec<u8> = (0..SIM_HEIGHTS.len() + 2).map(|_| link.exchange_byte(0x00).unwrap()).collect();
+        assert_eq!(heights[..SIM_HEIGHTS.len()], SIM_HEIGHTS);
+        assert_eq!(heights[SIM_HEIGHTS.len()..], SIM_HEIGHTS[..2]);
+
+        link.reset_link().unwrap();
+        assert_eq!(link.exchange_byte(0x00), Ok(SIM_HEIGHTS[0]));
+        assert_eq!(link.stats().exchanges.load(Ordering::Relaxed), SIM_HEIGHTS.len() as u64 + 3);
+    }
+}
@@ -1,91 +1,213 @@
 #![windows_subsystem = "windows"]
 
 mod bgb;
+mod bridge;
+mod clock;
+#[cfg(test)]
+mod fake_bgb;
 mod game;
+mod link;
+mod metrics;
 mod protocol;
+mod relay;
+mod settings;
+mod speed;
+mod trace;
+mod verbosity;
 mod websocket;
 
+use std::collections::VecDeque;
 use std::sync::mpsc;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
 use std::io::Write as IoWrite;
 use eframe::egui;
+use bgb::BgbEndpoint;
+use settings::Settings;
+use speed::Speed;
+use verbosity::Verbosity;
 use websocket::{WsCommand, WsEvent};
 
+/// Start when stopped, Stop when running.
+const START_STOP_SHORTCUT: egui::KeyboardShortcut =
+    egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::S);
+/// Toggle verbose logging.
+const VERBOSE_SHORTCUT: egui::KeyboardShortcut =
+    egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::L);
+
+/// Default host for BGB's link cable server.
+const BGB_HOST: &str = "127.0.0.1";
+/// Default port for BGB's link cable server.
+const BGB_PORT: u16 = 8765;
+/// Bytes each direction of the byte trace panel shows.
+const BYTE_TRACE_SHOWN: usize = 32;
+
 fn main() -> eframe::Result {
+    // Headless protocol trace for CI; everything else opens the GUI
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(("trace", rest)) = args.split_first().map(|(cmd, rest)| (cmd.as_str(), rest)) {
+        std::process::exit(trace::run(rest, BGB_HOST, BGB_PORT));
+    }
+
     let options = eframe::NativeOptions {
+        // Default size for the first launch; afterwards eframe restores the
+        // saved window geometry from its storage.
         viewport: egui::ViewportBuilder::default().with_inner_size([400.0, 500.0]),
+        persist_window: true,
         ..Default::default()
     };
     eframe::run_native(
         "GB Bridge - BGB Emulator",
         options,
-        Box::new(|_cc| Ok(Box::new(BridgeApp::default()))),
+        Box::new(|cc| Ok(Box::new(BridgeApp::new(cc)))),
     )
 }
 
 struct BridgeApp {
+    settings: Settings,
+    bgb_host: String,
     bgb_port: String,
+    /// Port of a second BGB to relay the link to; empty disables relay mode
+    relay_port: String,
     ws_port: String,
+    /// Unix socket path for BGB; overrides host/port when non-empty
+    #[cfg(unix)]
+    bgb_socket: String,
+    metrics_port: String,
     running: bool,
-    verbose: bool,
+    /// Verbose log categories, shared with the running bridge
+    verbosity: Verbosity,
+    /// Drive a simulated link instead of BGB
+    simulate: bool,
+    speed: Speed,
+    speed_value: f32,
     bgb_connected: bool,
+    bgb_version: Option<bgb::BgbVersion>,
+    /// Link stability since Start
+    bgb_uptime: LinkUptime,
     browser_connected: bool,
-    log: Vec<String>,
+    /// What the connected browser called itself, if it sent `identify`
+    browser_client: Option<websocket::ClientInfo>,
+    /// A match is being played; Stop asks for confirmation
+    in_match: bool,
+    /// The Stop confirmation window is open
+    confirm_stop: bool,
+    log: VecDeque<String>,
+    /// Newest link bytes sent and received, oldest first
+    sent_trace: VecDeque<u8>,
+    received_trace: VecDeque<u8>,
+    /// Case-insensitive substring the log view is filtered by (view only)
+    log_filter: String,
     cmd_tx: Option<mpsc::Sender<WsCommand>>,
     event_rx: Option<mpsc::Receiver<WsEvent>>,
-    verbose_flag: Option<Arc<AtomicBool>>,
     log_file: Option<std::io::BufWriter<std::fs::File>>,
+    /// Name of the current (or last) session's log file
+    log_path: Option<String>,
+    /// When writing the log file last failed; file logging is off since
+    log_failed_at: Option<std::time::Instant>,
     start_instant: Option<std::time::Instant>,
+    notify_match_end: bool,
+    last_notification: Option<std::time::Instant>,
+    lan_ip: Option<std::net::IpAddr>,
 }
 
 impl Default for BridgeApp {
     fn default() -> Self {
         Self {
-            bgb_port: "8765".into(),
+            settings: Settings::default(),
+            bgb_host: BGB_HOST.into(),
+            bgb_port: BGB_PORT.to_string(),
+            relay_port: String::new(),
             ws_port: "8767".into(),
+            #[cfg(unix)]
+            bgb_socket: String::new(),
+            metrics_port: String::new(),
             running: false,
-            verbose: false,
+            verbosity: Verbosity::default(),
+            simulate: false,
+            speed: Speed::default(),
+            speed_value: 1.0,
             bgb_connected: false,
+            bgb_version: None,
+            bgb_uptime: LinkUptime::default(),
             browser_connected: false,
-            log: vec!["Ready. Configure ports and click Start.".into()],
+            browser_client: None,
+            in_match: false,
+            confirm_stop: false,
+            log: VecDeque::from(["Ready. Configure ports and click Start.".to_string()]),
+            sent_trace: VecDeque::new(),
+            received_trace: VecDeque::new(),
+            log_filter: String::new(),
             cmd_tx: None,
             event_rx: None,
-            verbose_flag: None,
             log_file: None,
+            log_path: None,
+            log_failed_at: None,
             start_instant: None,
+            notify_match_end: true,
+            last_notification: None,
+            lan_ip: lan_ip(),
         }
     }
 }
 
 impl BridgeApp {
+    fn new(cc: &eframe::CreationContext<'_>) -> Self {
+        let settings = Settings::load(cc.storage);
+        cc.egui_ctx.set_visuals(theme_visuals(settings.dark_mode));
+        Self { settings, ..Default::default() }
+    }
+
     fn start(&mut self) {
         let ws_port: u16 = match self.ws_port.parse() {
             Ok(p) => p,
-            Err(_) => { self.log.push("Invalid WebSocket port".into()); return; }
+            Err(_) => { self.push_log("Invalid WebSocket port"); return; }
         };
         let bgb_port: u16 = match self.bgb_port.parse() {
             Ok(p) => p,
-            Err(_) => { self.log.push("Invalid BGB port".into()); return; }
+            Err(_) => { self.push_log("Invalid BGB port"); return; }
+        };
+        if let Err(e) = check_ports(ws_port, bgb_port) {
+            self.push_log(e);
+            return;
+        }
+        if !valid_host(self.bgb_host.trim()) {
+            self.push_log("Invalid BGB host");
+            return;
+        }
+        let relay_port: Option<u16> = match self.relay_port.trim() {
+            "" => None,
+            text => match text.parse() {
+                Ok(p) => Some(p),
+                Err(_) => { self.push_log("Invalid relay port"); return; }
+            },
+        };
+        // Metrics are optional: an empty field disables them
+        let metrics_port: Option<u16> = match self.metrics_port.trim() {
+            "" => None,
+            text => match text.parse() {
+                Ok(p) => Some(p),
+                Err(_) => { self.push_log("Invalid metrics port"); return; }
+            },
         };
 
         let (event_tx, event_rx) = mpsc::channel();
         let (cmd_tx, cmd_rx) = mpsc::channel();
 
-        let verbose_flag = Arc::new(AtomicBool::new(self.verbose));
-        self.verbose_flag = Some(verbose_flag.clone());
-
         // Open log file
         let start_instant = std::time::Instant::now();
         self.start_instant = Some(start_instant);
-        match std::fs::File::create("bgb-bridge.log") {
-            Ok(f) => {
+        match create_log_file() {
+            Ok((f, log_path)) => {
                 let mut writer = std::io::BufWriter::new(f);
                 let _ = writeln!(writer, "=== BGB Bridge Log ===");
                 self.log_file = Some(writer);
+                self.log_path = Some(log_path);
+                self.log_failed_at = None;
+                if let Err(e) = prune_logs(self.settings.log_files_kept) {
+                    self.push_log(format!("Warning: could not prune old log files: {}", e));
+                }
             }
             Err(e) => {
-                self.log.push(format!("Warning: could not create log file: {}", e));
+                self.push_log(format!("Warning: could not create log file: {}", e));
             }
         }
 
@@ -93,25 +215,149 @@ impl BridgeApp {
         self.cmd_tx = Some(cmd_tx);
         self.running = true;
         self.bgb_connected = false;
+        self.bgb_uptime = LinkUptime::default();
+        self.sent_trace.clear();
+        self.received_trace.clear();
         self.browser_connected = false;
-        self.log.push(format!("Starting... WS:{} BGB:{}", ws_port, bgb_port));
+        self.push_log(format!("Starting... WS:{} BGB:{}", ws_port, bgb_port));
         self.write_log("Starting bridge");
 
-        let bgb_host = "127.0.0.1".to_string();
+        let config = websocket::ServerConfig {
+            ws_port,
+            bgb: self.bgb_endpoint(bgb_port),
+            relay: relay_port.map(|port| BgbEndpoint::Tcp { host: self.bgb_host.trim().to_string(), port }),
+            metrics_port,
+            verbose: self.verbosity.clone(),
+            speed: self.speed.clone(),
+            game: game::GameConfig {
+                idle_timeout: match self.settings.idle_timeout_mins {
+                    0 => None,
+                    mins => Some(std::time::Duration::from_secs(mins as u64 * 60)),
+                },
+                max_session: match self.settings.max_match_mins {
+                    0 => None,
+                    mins => Some(std::time::Duration::from_secs(mins as u64 * 60)),
+                },
+                ..Default::default()
+            },
+            simulate: self.simulate,
+            lazy_bgb: self.settings.lazy_bgb,
+            bgb_poll: bgb::PollInterval {
+                max: std::time::Duration::from_millis(self.settings.bgb_idle_poll_ms.max(1)),
+                keepalive: match self.settings.bgb_keepalive_secs {
+                    0 => None,
+                    secs => Some(std::time::Duration::from_secs(secs)),
+                },
+                ..Default::default()
+            },
+            bgb_status: protocol::StatusFlags::default(),
+            bgb_timeouts: bgb::Timeouts {
+                handshake: std::time::Duration::from_millis(self.settings.bgb_handshake_timeout_ms.max(1)),
+                exchange: std::time::Duration::from_millis(self.settings.bgb_exchange_timeout_ms.max(1)),
+            },
+            reconnect_grace: match self.settings.reconnect_grace_secs {
+                0 => None,
+                secs => Some(std::time::Duration::from_secs(secs)),
+            },
+        };
         std::thread::spawn(move || {
-            websocket::run(ws_port, bgb_host, bgb_port, event_tx, cmd_rx, verbose_flag);
+            websocket::run(config, event_tx, cmd_rx);
         });
     }
 
+    fn bgb_endpoint(&self, bgb_port: u16) -> BgbEndpoint {
+        #[cfg(unix)]
+        if !self.bgb_socket.trim().is_empty() {
+            return BgbEndpoint::Unix(self.bgb_socket.trim().into());
+        }
+        BgbEndpoint::Tcp { host: self.bgb_host.trim().to_string(), port: bgb_port }
+    }
+
+    /// Stop the bridge, asking first if that would abandon a match.
     fn stop(&mut self) {
+        if self.in_match {
+            self.confirm_stop = true;
+        } else {
+            self.stop_now();
+        }
+    }
+
+    fn stop_now(&mut self) {
+        self.confirm_stop = false;
         if let Some(tx) = &self.cmd_tx {
             let _ = tx.send(WsCommand::Stop);
         }
-        self.log.push("Stop requested...".into());
+        self.push_log("Stop requested...");
         self.write_log("Stop requested");
         // Flush and close log file
-        if let Some(ref mut f) = self.log_file {
-            let _ = f.flush();
+        self.flush_log();
+    }
+
+    /// Turn every verbose log category on or off.
+    fn set_verbose(&mut self, verbose: bool) {
+        self.verbosity.set(if verbose { Verbosity::ALL } else { 0 });
+    }
+
+    /// Global shortcuts. Consumed before any widget runs, so they work
+    /// even while a text field has focus.
+    fn handle_shortcuts(&mut self, ctx: &egui::Context) {
+        if ctx.input_mut(|i| i.consume_shortcut(&START_STOP_SHORTCUT)) {
+            if self.running {
+                self.stop();
+            } else {
+                self.start();
+            }
+        }
+        if ctx.input_mut(|i| i.consume_shortcut(&VERBOSE_SHORTCUT)) {
+            self.set_verbose(self.verbosity.get() == 0);
+        }
+    }
+
+    /// Check the WebSocket port is free and BGB is reachable, without
+    /// starting the bridge.
+    fn check_ports(&mut self) {
+        match self.ws_port.parse::<u16>() {
+            Ok(port) => match std::net::TcpListener::bind((websocket::WS_BIND_HOST, port)) {
+                Ok(_) => self.push_log(format!("WebSocket port {} is free", port)),
+                Err(e) => self.push_log(format!("WebSocket port {} unavailable: {}", port, e)),
+            },
+            Err(_) => self.push_log("Invalid WebSocket port"),
+        }
+        let bgb_port: u16 = match self.bgb_port.parse() {
+            Ok(p) => p,
+            Err(_) => { self.push_log("Invalid BGB port"); return; }
+        };
+        if !valid_host(self.bgb_host.trim()) {
+            self.push_log("Invalid BGB host");
+            return;
+        }
+        let endpoint = self.bgb_endpoint(bgb_port);
+        match endpoint.probe() {
+            Ok(()) => self.push_log(format!("BGB is listening at {}", endpoint)),
+            Err(e) => self.push_log(format!("BGB not reachable: {}", e)),
+        }
+    }
+
+    fn self_test(&mut self) {
+        if let Some(tx) = &self.cmd_tx {
+            let _ = tx.send(WsCommand::SelfTest);
+        }
+        self.push_log("Testing BGB connection...");
+    }
+
+    fn reconnect_bgb(&mut self) {
+        if let Some(tx) = &self.cmd_tx {
+            let _ = tx.send(WsCommand::ReconnectBgb);
+        }
+        self.push_log("Reconnecting to BGB...");
+    }
+
+    /// Append a line to the on-screen log, evicting the oldest lines beyond
+    /// the configured capacity.
+    fn push_log(&mut self, msg: impl Into<String>) {
+        self.log.push_back(msg.into());
+        while self.log.len() > self.settings.log_capacity.max(1) {
+            self.log.pop_front();
         }
     }
 
@@ -120,11 +366,73 @@ impl BridgeApp {
             let elapsed = start.elapsed();
             let secs = elapsed.as_secs();
             let millis = elapsed.subsec_millis();
-            let _ = writeln!(f, "[{:02}:{:02}:{:02}.{:03}] {}",
-                secs / 3600, (secs % 3600) / 60, secs % 60, millis, msg);
+            // Elapsed first for existing log readers, then UTC wall-clock time
+            // for lining up with BGB and browser console logs
+            let result = writeln!(f, "[{:02}:{:02}:{:02}.{:03}] [{}] {}",
+                secs / 3600, (secs % 3600) / 60, secs % 60, millis, utc_timestamp(), msg);
+            self.check_log_write(result);
         }
     }
 
+    fn flush_log(&mut self) {
+        if let Some(ref mut f) = self.log_file {
+            let result = f.flush();
+            self.check_log_write(result);
+        }
+    }
+
+    /// Stop file logging after the first failed write instead of failing
+    /// silently on every line.
+    fn check_log_write(&mut self, result: std::io::Result<()>) {
+        if let Err(e) = result {
+            self.log_file = None;
+            self.log_failed_at = Some(std::time::Instant::now());
+            self.push_log(format!("Log file write failed: {}, disabling file logging", e));
+        }
+    }
+
+    /// With `reopen_log` set, try appending to the session's log file again
+    /// once `LOG_REOPEN_INTERVAL` has passed since the last failure.
+    fn retry_log_file(&mut self) {
+        let (Some(failed_at), Some(path)) = (self.log_failed_at, &self.log_path) else { return };
+        if !self.settings.reopen_log || failed_at.elapsed() < LOG_REOPEN_INTERVAL {
+            return;
+        }
+        match std::fs::OpenOptions::new().append(true).open(path) {
+            Ok(f) => {
+                let line = format!("Reopened log file {}", path);
+                self.log_file = Some(std::io::BufWriter::new(f));
+                self.log_failed_at = None;
+                self.write_log(&line);
+                self.push_log(line);
+            }
+            Err(_) => self.log_failed_at = Some(std::time::Instant::now()),
+        }
+    }
+
+    /// Show a desktop notification for the end of a match. Duplicate
+    /// win/lose events arriving within a few seconds are collapsed.
+    fn notify_match_ended(&mut self, won: bool) {
+        if !self.notify_match_end || !self.running {
+            return;
+        }
+        if let Some(last) = self.last_notification {
+            if last.elapsed() < std::time::Duration::from_secs(5) {
+                return;
+            }
+        }
+        self.last_notification = Some(std::time::Instant::now());
+
+        let body = if won { "You won!" } else { "You lost" };
+        // Showing a notification can block on the desktop bus, keep it off the UI thread
+        std::thread::spawn(move || {
+            let _ = notify_rust::Notification::new()
+                .summary("GB Bridge")
+                .body(body)
+                .show();
+        });
+    }
+
     fn poll_events(&mut self) {
         // Take the receiver out temporarily to avoid borrow conflict
         let rx = match self.event_rx.take() {
@@ -136,27 +444,62 @@ impl BridgeApp {
             match event {
                 WsEvent::Log(msg) => {
                     self.write_log(&msg);
-                    self.log.push(msg);
-                    // Cap log to prevent unbounded memory growth
-                    if self.log.len() > 500 {
-                        self.log.drain(..self.log.len() - 300);
-                    }
+                    self.push_log(msg);
                 }
                 WsEvent::BrowserConnected => self.browser_connected = true,
-                WsEvent::BrowserDisconnected => self.browser_connected = false,
-                WsEvent::BgbConnected => self.bgb_connected = true,
-                WsEvent::BgbDisconnected => self.bgb_connected = false,
-                WsEvent::Stopped => {
+                WsEvent::BrowserIdentified(client) => self.browser_client = Some(client),
+                WsEvent::BrowserDisconnected => {
+                    self.browser_connected = false;
+                    self.browser_client = None;
+                    self.in_match = false;
+                }
+                WsEvent::BgbConnected(version) => {
+                    self.bgb_connected = true;
+                    self.bgb_version = Some(version);
+                    self.bgb_uptime.connected();
+                }
+                WsEvent::BgbDisconnected => {
+                    self.bgb_connected = false;
+                    self.bgb_uptime.disconnected();
+                }
+                WsEvent::ByteTrace { dir, byte } => {
+                    let trace = match dir {
+                        bgb::Direction::Sent => &mut self.sent_trace,
+                        bgb::Direction::Received => &mut self.received_trace,
+                    };
+                    if trace.len() == BYTE_TRACE_SHOWN {
+                        trace.pop_front();
+                    }
+                    trace.push_back(byte);
+                }
+                WsEvent::MatchStarted => self.in_match = true,
+                WsEvent::MatchEnded { won } => {
+                    self.in_match = false;
+                    self.notify_match_ended(won);
+                }
+                WsEvent::MatchAborted => {
+                    if self.in_match {
+                        self.push_log("Match ended without a result");
+                    }
+                    self.in_match = false;
+                }
+                WsEvent::Stopped { reason, last_disconnect, exchanges } => {
                     self.running = false;
                     self.bgb_connected = false;
+                    self.bgb_uptime.disconnected();
                     self.browser_connected = false;
+                    self.browser_client = None;
+                    self.in_match = false;
+                    self.confirm_stop = false;
                     self.cmd_tx = None;
-                    self.log.push("Stopped.".into());
-                    self.write_log("Stopped");
-                    if let Some(ref mut f) = self.log_file {
-                        let _ = f.flush();
+                    let mut line = format!("Stopped ({}", reason);
+                    if let Some(last) = last_disconnect {
+                        line.push_str(&format!(", last disconnect: {}", last));
                     }
-                    self.verbose_flag = None;
+                    line.push_str(&format!(", {} exchanges).", exchanges));
+                    self.write_log(&line);
+                    self.push_log(line);
+                    self.flush_log();
                     // Don't put rx back — we're stopped
                     return;
                 }
@@ -167,15 +510,218 @@ impl BridgeApp {
         self.event_rx = Some(rx);
 
         // Periodically flush log file
-        if let Some(ref mut f) = self.log_file {
-            let _ = f.flush();
+        self.flush_log();
+        self.retry_log_file();
+    }
+}
+
+/// How long BGB has been connected during one run, and how often the
+/// connection was re-established.
+#[derive(Default)]
+struct LinkUptime {
+    /// Time connected in sessions that already ended
+    previous: std::time::Duration,
+    /// When the current connection was made
+    since: Option<std::time::Instant>,
+    connects: u32,
+}
+
+impl LinkUptime {
+    fn connected(&mut self) {
+        self.disconnected();
+        self.since = Some(std::time::Instant::now());
+        self.connects += 1;
+    }
+
+    fn disconnected(&mut self) {
+        if let Some(since) = self.since.take() {
+            self.previous += since.elapsed();
         }
     }
+
+    fn total(&self) -> std::time::Duration {
+        self.previous + self.since.map_or(std::time::Duration::ZERO, |s| s.elapsed())
+    }
+
+    fn reconnects(&self) -> u32 {
+        self.connects.saturating_sub(1)
+    }
+}
+
+/// Compact duration such as "45s", "3m12s" or "2h05m".
+fn format_duration(d: std::time::Duration) -> String {
+    let secs = d.as_secs();
+    if secs < 60 {
+        format!("{}s", secs)
+    } else if secs < 3600 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h{:02}m", secs / 3600, (secs % 3600) / 60)
+    }
+}
+
+/// Current UTC time as `YYYY-MM-DDTHH:MM:SS.mmmZ`.
+fn utc_timestamp() -> String {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let (year, month, day, day_secs) = civil_date(now.as_secs());
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day, day_secs / 3600, (day_secs % 3600) / 60, day_secs % 60, now.subsec_millis())
+}
+
+/// How long to wait after a failed log write before reopening the file.
+const LOG_REOPEN_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Prefix and suffix of per-session log files.
+const LOG_PREFIX: &str = "bgb-bridge-";
+const LOG_SUFFIX: &str = ".log";
+
+/// Log file for a session starting now, e.g. `bgb-bridge-20240601-143022.log`
+/// (UTC). Names sort in start order.
+fn log_file_name() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (year, month, day, day_secs) = civil_date(secs);
+    format!("{}{:04}{:02}{:02}-{:02}{:02}{:02}{}", LOG_PREFIX,
+        year, month, day, day_secs / 3600, (day_secs % 3600) / 60, day_secs % 60, LOG_SUFFIX)
+}
+
+/// Create a new session log without touching existing ones: a session
+/// started in the same second as the last gets a `_2` (`_3`, ...) suffix.
+fn create_log_file() -> std::io::Result<(std::fs::File, String)> {
+    let name = log_file_name();
+    let stem = name.strip_suffix(LOG_SUFFIX).unwrap_or(&name);
+    let mut n = 1;
+    loop {
+        let path = match n {
+            1 => name.clone(),
+            _ => format!("{}_{}{}", stem, n, LOG_SUFFIX),
+        };
+        match std::fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(f) => return Ok((f, path)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && n < 100 => n += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Delete all but the newest `keep` session logs in the working directory.
+/// 0 keeps everything.
+fn prune_logs(keep: usize) -> std::io::Result<()> {
+    if keep == 0 {
+        return Ok(());
+    }
+    let mut logs: Vec<String> = std::fs::read_dir(".")?
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| name.starts_with(LOG_PREFIX) && name.ends_with(LOG_SUFFIX))
+        .collect();
+    logs.sort();
+    let excess = logs.len().saturating_sub(keep);
+    for name in &logs[..excess] {
+        std::fs::remove_file(name)?;
+    }
+    Ok(())
+}
+
+/// Split seconds since the Unix epoch into UTC (year, month, day, seconds
+/// into the day).
+fn civil_date(secs: u64) -> (i64, i64, i64, u64) {
+    let (days, day_secs) = (secs / 86_400, secs % 86_400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day, day_secs)
+}
+
+/// Best-effort lookup of this machine's LAN address. Connecting a UDP socket
+/// only selects a route; no packets are sent.
+fn lan_ip() -> Option<std::net::IpAddr> {
+    let socket = std::net::UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("8.8.8.8:80").ok()?;
+    let ip = socket.local_addr().ok()?.ip();
+    if ip.is_unspecified() || ip.is_loopback() { None } else { Some(ip) }
+}
+
+/// Catch port mix-ups before binding anything: port 0 would bind a random
+/// port the browser can't know, and a shared port has the bridge connecting
+/// to its own WebSocket listener instead of BGB.
+fn check_ports(ws_port: u16, bgb_port: u16) -> Result<(), String> {
+    if ws_port == 0 {
+        return Err("WebSocket port must not be 0".into());
+    }
+    if bgb_port == 0 {
+        return Err("BGB port must not be 0".into());
+    }
+    if ws_port == bgb_port {
+        return Err(format!("WebSocket and BGB ports are both {}; they must differ", ws_port));
+    }
+    Ok(())
+}
+
+/// An IP address or something shaped like a DNS hostname.
+fn valid_host(host: &str) -> bool {
+    if host.parse::<std::net::IpAddr>().is_ok() {
+        return true;
+    }
+    !host.is_empty()
+        && host.len() <= 253
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+fn theme_visuals(dark_mode: bool) -> egui::Visuals {
+    if dark_mode { egui::Visuals::dark() } else { egui::Visuals::light() }
+}
+
+/// Connected/Disconnected label with colors legible on both themes.
+fn status_label(ui: &mut egui::Ui, connected: bool) {
+    if connected {
+        let green = if ui.visuals().dark_mode { egui::Color32::GREEN } else { egui::Color32::DARK_GREEN };
+        ui.colored_label(green, "Connected");
+    } else {
+        let gray = ui.visuals().weak_text_color();
+        ui.colored_label(gray, "Disconnected");
+    }
+}
+
+/// Bytes as space-separated hex, e.g. `29 55 02`.
+fn hex_bytes(bytes: &VecDeque<u8>) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ")
+}
+
+/// A read-only address line with a copy-to-clipboard button.
+fn address_row(ui: &mut egui::Ui, label: &str, value: &str) {
+    ui.horizontal(|ui| {
+        ui.label(label);
+        ui.monospace(value);
+        if ui.small_button("Copy").clicked() {
+            ui.output_mut(|o| o.copied_text = value.to_string());
+        }
+    });
 }
 
 impl eframe::App for BridgeApp {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        self.settings.save(storage);
+    }
+
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         self.poll_events();
+        self.handle_shortcuts(ctx);
 
         // Request repaint periodically to pick up events from the bridge thread
         if self.running {
@@ -183,10 +729,29 @@ impl eframe::App for BridgeApp {
         }
 
         egui::CentralPanel::default().show(ctx, |ui| {
-            ui.heading("GB Bridge - BGB Emulator");
+            ui.horizontal(|ui| {
+                ui.heading("GB Bridge - BGB Emulator");
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    let label = if self.settings.dark_mode { "Light Theme" } else { "Dark Theme" };
+                    if ui.small_button(label).clicked() {
+                        self.settings.dark_mode = !self.settings.dark_mode;
+                        ctx.set_visuals(theme_visuals(self.settings.dark_mode));
+                    }
+                });
+            });
             ui.add_space(8.0);
 
             // Port configuration
+            ui.horizontal(|ui| {
+                ui.label("BGB Host:");
+                ui.add_enabled(!self.running, egui::TextEdit::singleline(&mut self.bgb_host).desired_width(120.0));
+                ui.add_space(16.0);
+                ui.label("Relay Port:");
+                ui.add_enabled(!self.running, egui::TextEdit::singleline(&mut self.relay_port)
+                    .hint_text("off")
+                    .desired_width(60.0))
+                    .on_hover_text("Port of a second BGB on the same host. The bridge then links the two emulators together instead of playing from the browser.");
+            });
             ui.horizontal(|ui| {
                 ui.label("BGB Port:");
                 ui.add_enabled(!self.running, egui::TextEdit::singleline(&mut self.bgb_port).desired_width(60.0));
@@ -194,26 +759,146 @@ impl eframe::App for BridgeApp {
                 ui.label("WS Port:");
                 ui.add_enabled(!self.running, egui::TextEdit::singleline(&mut self.ws_port).desired_width(60.0));
             });
+            #[cfg(unix)]
+            ui.horizontal(|ui| {
+                ui.label("BGB Socket:");
+                ui.add_enabled(!self.running, egui::TextEdit::singleline(&mut self.bgb_socket)
+                    .hint_text("optional Unix socket path")
+                    .desired_width(200.0));
+            });
+            // Wraps: more settings than fit on one line at the default width
+            ui.horizontal_wrapped(|ui| {
+                ui.label("Metrics Port:");
+                ui.add_enabled(!self.running, egui::TextEdit::singleline(&mut self.metrics_port)
+                    .hint_text("off")
+                    .desired_width(60.0));
+                ui.add_space(16.0);
+                ui.label("Idle Stop:");
+                ui.add_enabled(!self.running, egui::DragValue::new(&mut self.settings.idle_timeout_mins)
+                    .range(0..=24 * 60)
+                    .custom_formatter(|n, _| if n == 0.0 { "off".into() } else { format!("{} min", n) }));
+                ui.add_space(16.0);
+                ui.label("Max Match:");
+                ui.add_enabled(!self.running, egui::DragValue::new(&mut self.settings.max_match_mins)
+                    .range(0..=24 * 60)
+                    .custom_formatter(|n, _| if n == 0.0 { "off".into() } else { format!("{} min", n) }))
+                    .on_hover_text("End a match that runs this long, so a stuck game can't hold BGB forever");
+                ui.add_space(16.0);
+                ui.label("Reconnect Grace:");
+                ui.add_enabled(!self.running, egui::DragValue::new(&mut self.settings.reconnect_grace_secs)
+                    .range(0..=600)
+                    .custom_formatter(|n, _| if n == 0.0 { "off".into() } else { format!("{} s", n) }))
+                    .on_hover_text("Keep a game running this long after the browser drops, so a reconnecting browser can resume it");
+                ui.add_space(16.0);
+                ui.label("Idle Poll:");
+                ui.add_enabled(!self.running, egui::DragValue::new(&mut self.settings.bgb_idle_poll_ms)
+                    .range(1..=100)
+                    .suffix(" ms"))
+                    .on_hover_text("Longest wait between BGB polls while idle. Higher saves CPU, lower reacts faster.");
+                ui.add_space(16.0);
+                ui.label("Keepalive:");
+                ui.add_enabled(!self.running, egui::DragValue::new(&mut self.settings.bgb_keepalive_secs)
+                    .range(0..=300)
+                    .custom_formatter(|n, _| if n == 0.0 { "off".into() } else { format!("{} s", n) }))
+                    .on_hover_text("Send BGB a status packet after this long without link traffic, so it doesn't drop an idle link");
+                ui.add_space(16.0);
+                ui.label("Timeouts:");
+                ui.add_enabled(!self.running, egui::DragValue::new(&mut self.settings.bgb_handshake_timeout_ms)
+                    .range(100..=30_000)
+                    .speed(50)
+                    .suffix(" ms"))
+                    .on_hover_text("How long BGB gets to answer when connecting. Short fails fast if BGB isn't ready.");
+                ui.add_enabled(!self.running, egui::DragValue::new(&mut self.settings.bgb_exchange_timeout_ms)
+                    .range(100..=120_000)
+                    .speed(50)
+                    .suffix(" ms"))
+                    .on_hover_text("How long BGB gets to answer each link byte. Raise it to step through a paused emulator.");
+                ui.add_space(16.0);
+                ui.label("Keep Logs:");
+                ui.add_enabled(!self.running, egui::DragValue::new(&mut self.settings.log_files_kept)
+                    .range(0..=1000)
+                    .custom_formatter(|n, _| if n == 0.0 { "all".into() } else { format!("{}", n) }))
+                    .on_hover_text(format!("Session log files to keep. Starting a session deletes older {}*{} files \
+                        in the working directory without asking, including copies you left there.", LOG_PREFIX, LOG_SUFFIX));
+                ui.checkbox(&mut self.settings.reopen_log, "Reopen on Error")
+                    .on_hover_text("After a failed log write, retry the log file every few seconds instead of giving up.");
+            });
+
+            // Resolved addresses
+            address_row(ui, "Browser URL:", &format!("ws://localhost:{}", self.ws_port));
+            if websocket::WS_BIND_HOST != "127.0.0.1" {
+                if let Some(ip) = self.lan_ip {
+                    address_row(ui, "LAN URL:", &format!("ws://{}:{}", ip, self.ws_port));
+                }
+            }
+            let bgb_target = match self.bgb_port.parse() {
+                Ok(port) => self.bgb_endpoint(port).to_string(),
+                Err(_) => format!("{}:{}", self.bgb_host.trim(), self.bgb_port),
+            };
+            address_row(ui, "BGB Target:", &bgb_target);
 
             ui.add_space(8.0);
 
             // Start/Stop and Verbose
             ui.horizontal(|ui| {
+                let start_stop_hint = ctx.format_shortcut(&START_STOP_SHORTCUT);
                 if self.running {
-                    if ui.button("Stop").clicked() {
+                    if ui.button("Stop").on_hover_text(start_stop_hint).clicked() {
                         self.stop();
                     }
-                } else if ui.button("Start").clicked() {
+                } else if ui.button("Start").on_hover_text(start_stop_hint).clicked() {
                     self.start();
                 }
 
+                if ui.add_enabled(self.running, egui::Button::new("Test Connection")).clicked() {
+                    self.self_test();
+                }
+
+                if ui.add_enabled(!self.running, egui::Button::new("Check Ports")).clicked() {
+                    self.check_ports();
+                }
+
                 ui.add_space(16.0);
 
-                if ui.checkbox(&mut self.verbose, "Verbose Logs").changed() {
-                    if let Some(ref flag) = self.verbose_flag {
-                        flag.store(self.verbose, Ordering::Relaxed);
+                // The browser may change the mask too, so read it every frame
+                let mask = self.verbosity.get();
+                let mut verbose = mask != 0;
+                if ui.checkbox(&mut verbose, "Verbose Logs")
+                    .on_hover_text(ctx.format_shortcut(&VERBOSE_SHORTCUT))
+                    .changed()
+                {
+                    self.set_verbose(verbose);
+                }
+                ui.menu_button("▾", |ui| {
+                    for (bit, name) in Verbosity::CATEGORIES {
+                        let mut on = mask & bit != 0;
+                        if ui.checkbox(&mut on, name).changed() {
+                            self.verbosity.set(mask ^ bit);
+                        }
                     }
+                }).response.on_hover_text("Choose which verbose categories are logged");
+
+                ui.add_space(16.0);
+                ui.checkbox(&mut self.notify_match_end, "Notify on Win/Lose");
+            });
+
+            // Emulation speed (the browser may also change it while running)
+            ui.horizontal(|ui| {
+                ui.label("Speed:");
+                self.speed_value = self.speed.get();
+                let drag = egui::DragValue::new(&mut self.speed_value)
+                    .range(Speed::MIN..=Speed::MAX)
+                    .speed(0.05)
+                    .suffix("x");
+                if ui.add(drag).changed() {
+                    self.speed.set(self.speed_value);
                 }
+
+                ui.add_space(16.0);
+                ui.add_enabled(!self.running, egui::Checkbox::new(&mut self.simulate, "Simulate (no BGB)"))
+                    .on_hover_text("Log link bytes and answer with canned responses instead of connecting to BGB");
+                ui.add_enabled(!self.running, egui::Checkbox::new(&mut self.settings.lazy_bgb, "Lazy BGB"))
+                    .on_hover_text("Connect to BGB only once a game starts, and let go of it after each match");
             });
 
             ui.add_space(12.0);
@@ -223,17 +908,42 @@ impl eframe::App for BridgeApp {
             // Status indicators
             ui.horizontal(|ui| {
                 ui.label("BGB:");
-                if self.bgb_connected {
-                    ui.colored_label(egui::Color32::GREEN, "Connected");
-                } else {
-                    ui.colored_label(egui::Color32::GRAY, "Disconnected");
+                status_label(ui, self.bgb_connected);
+                if let (true, Some(version)) = (self.bgb_connected, self.bgb_version) {
+                    ui.weak(format!("(v{}.{})", version.major, version.minor))
+                        .on_hover_text(version.to_string());
+                }
+                if self.running && !self.bgb_connected && ui.small_button("Reconnect BGB")
+                    .on_hover_text("Open a new link to BGB, keeping the browser connected")
+                    .clicked()
+                {
+                    self.reconnect_bgb();
                 }
                 ui.add_space(24.0);
                 ui.label("Browser:");
-                if self.browser_connected {
-                    ui.colored_label(egui::Color32::GREEN, "Connected");
-                } else {
-                    ui.colored_label(egui::Color32::GRAY, "Disconnected");
+                status_label(ui, self.browser_connected);
+                if let (true, Some(client)) = (self.browser_connected, &self.browser_client) {
+                    ui.weak(format!("({})", client));
+                }
+            });
+            if self.bgb_uptime.connects > 0 {
+                let uptime = &self.bgb_uptime;
+                let mut line = format!("BGB uptime {}, {} reconnect(s)",
+                    format_duration(uptime.total()), uptime.reconnects());
+                if let (true, Some(since)) = (uptime.reconnects() > 0, uptime.since) {
+                    line.push_str(&format!(", last {} ago", format_duration(since.elapsed())));
+                }
+                ui.weak(line);
+            }
+            if let Some(path) = &self.log_path {
+                ui.weak(format!("Log file: {}", path));
+            }
+            egui::CollapsingHeader::new("Byte Trace").show(ui, |ui| {
+                for (label, trace) in [("Sent:", &self.sent_trace), ("Received:", &self.received_trace)] {
+                    ui.horizontal(|ui| {
+                        ui.label(label);
+                        ui.monospace(hex_bytes(trace));
+                    });
                 }
             });
 
@@ -242,15 +952,60 @@ impl eframe::App for BridgeApp {
             ui.add_space(4.0);
 
             // Log area
-            ui.label("Log:");
+            ui.horizontal(|ui| {
+                ui.label("Log:");
+                ui.add(egui::TextEdit::singleline(&mut self.log_filter)
+                    .hint_text("filter")
+                    .desired_width(120.0));
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    ui.add(egui::DragValue::new(&mut self.settings.log_capacity).range(50..=100_000));
+                    ui.label("Max lines:");
+                });
+            });
+            let filter = self.log_filter.trim().to_lowercase();
             egui::ScrollArea::vertical()
-                .stick_to_bottom(true)
+                // Only follow new lines when showing the whole log
+                .stick_to_bottom(filter.is_empty())
                 .max_height(280.0)
                 .show(ui, |ui| {
                     for line in &self.log {
-                        ui.label(line);
+                        if filter.is_empty() || line.to_lowercase().contains(&filter) {
+                            ui.label(line);
+                        }
                     }
                 });
         });
+
+        if self.confirm_stop {
+            egui::Window::new("Stop bridge?")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    ui.label("A match is in progress. Stop anyway?");
+                    ui.add_space(8.0);
+                    ui.horizontal(|ui| {
+                        if ui.button("Stop").clicked() {
+                            self.stop_now();
+                        }
+                        if ui.button("Cancel").clicked() {
+                            self.confirm_stop = false;
+                        }
+                    });
+                });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ports_must_be_nonzero_and_distinct() {
+        assert_eq!(check_ports(8767, 8765), Ok(()));
+        assert_eq!(check_ports(0, 8765).unwrap_err(), "WebSocket port must not be 0");
+        assert_eq!(check_ports(8767, 0).unwrap_err(), "BGB port must not be 0");
+        assert_eq!(check_ports(8765, 8765).unwrap_err(), "WebSocket and BGB ports are both 8765; they must differ");
     }
 }
@@ -0,0 +1,107 @@
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+use crate::bgb::LinkStats;
+
+/// Health counters for the whole bridge run, served as plaintext in the
+/// Prometheus exposition format.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Exchanges from BGB sessions that have already ended
+    past_exchanges: AtomicU64,
+    /// Stats of the live BGB session, if any
+    link: Mutex<Option<Arc<LinkStats>>>,
+    pub browser_connected: AtomicBool,
+    pub wins: AtomicU64,
+    pub losses: AtomicU64,
+}
+
+impl Metrics {
+    /// Start reporting a new BGB session's counters.
+    pub fn attach_link(&self, stats: Arc<LinkStats>) {
+        *self.link.lock().unwrap() = Some(stats);
+    }
+
+    /// Fold the live session's exchanges into the running total.
+    pub fn detach_link(&self) {
+        if let Some(stats) = self.link.lock().unwrap().take() {
+            self.past_exchanges.fetch_add(stats.exchanges.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+    }
+
+    /// Whether a BGB session is attached and its link is up.
+    pub fn bgb_connected(&self) -> bool {
+        self.link.lock().unwrap().as_ref().is_some_and(|s| s.connected.load(Ordering::Relaxed))
+    }
+
+    pub fn render(&self) -> String {
+        let link = self.link.lock().unwrap().clone();
+        let live_exchanges = link.as_ref().map_or(0, |s| s.exchanges.load(Ordering::Relaxed));
+        let latency = link.as_ref().map_or(0, |s| s.last_latency_ms.load(Ordering::Relaxed));
+        let bgb_connected = self.bgb_connected();
+
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value);
+        };
+        metric("bgb_bridge_exchanges_total", "counter", "Link byte exchanges initiated",
+            self.past_exchanges.load(Ordering::Relaxed) + live_exchanges);
+        metric("bgb_bridge_exchange_latency_ms", "gauge", "Round trip of the most recent exchange", latency);
+        metric("bgb_bridge_browser_connected", "gauge", "Whether a browser is connected",
+            self.browser_connected.load(Ordering::Relaxed) as u64);
+        metric("bgb_bridge_bgb_connected", "gauge", "Whether the BGB link is up", bgb_connected as u64);
+        metric("bgb_bridge_wins_total", "counter", "Matches won", self.wins.load(Ordering::Relaxed));
+        metric("bgb_bridge_losses_total", "counter", "Matches lost", self.losses.load(Ordering::Relaxed));
+        out
+    }
+}
+
+/// Serve `metrics` over HTTP on `port` until `stop` is set. Every request
+/// gets the metrics page regardless of path.
+pub fn serve(port: u16, metrics: Arc<Metrics>, stop: Arc<AtomicBool>, log_tx: mpsc::Sender<String>) {
+    let addr = format!("0.0.0.0:{}", port);
+    let listener = match TcpListener::bind(&addr) {
+        Ok(l) => l,
+        Err(e) => {
+            let _ = log_tx.send(format!("Metrics: failed to bind {}: {}", addr, e));
+            return;
+        }
+    };
+    listener.set_nonblocking(true).ok();
+    let _ = log_tx.send(format!("Metrics available at http://{}/metrics", addr));
+
+    while !stop.load(Ordering::Relaxed) {
+        let mut stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                std::thread::sleep(Duration::from_millis(50));
+                continue;
+            }
+            Err(e) => {
+                let _ = log_tx.send(format!("Metrics accept error: {}", e));
+                std::thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+        };
+
+        stream.set_nonblocking(false).ok();
+        let _ = stream.set_read_timeout(Some(Duration::from_millis(500)));
+        // Consume the request head; its contents don't matter
+        let mut buf = [0u8; 1024];
+        let _ = stream.read(&mut buf);
+
+        let body = metrics.render();
+        let _ = write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+    }
+}
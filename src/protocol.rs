@@ -5,7 +5,7 @@
 ///   104 = sync1 / master transfer (send a byte to the Game Boy)
 ///   105 = sync2 / slave response (Game Boy sends a byte back)
 ///   106 = sync3 / acknowledgement
-///   108 = status (running/paused flags)
+///   108 = status (running/paused flags, see `StatusFlags`)
 ///   109 = want disconnect
 #[derive(Debug, Clone, Copy)]
 pub struct BgbPacket {
@@ -21,7 +21,7 @@ impl BgbPacket {
         Self { command, data, extra1, extra2, timestamp }
     }
 
-    pub fn to_bytes(&self) -> [u8; 8] {
+    pub fn to_bytes(self) -> [u8; 8] {
         let ts = self.timestamp.to_le_bytes();
         [self.command, self.data, self.extra1, self.extra2, ts[0], ts[1], ts[2], ts[3]]
     }
@@ -35,4 +35,96 @@ impl BgbPacket {
             timestamp: u32::from_le_bytes([b[4], b[5], b[6], b[7]]),
         }
     }
+
+    /// Like `from_bytes`, but None unless the command byte is one we know.
+    /// A misaligned read almost always fails this check.
+    pub fn try_from_bytes(b: [u8; 8]) -> Option<Self> {
+        let pkt = Self::from_bytes(b);
+        pkt.command_name().map(|_| pkt)
+    }
+
+    /// Protocol name of the command byte, or None if it isn't one we know.
+    pub fn command_name(&self) -> Option<&'static str> {
+        match self.command {
+            1 => Some("version"),
+            104 => Some("sync1"),
+            105 => Some("sync2"),
+            106 => Some("sync3"),
+            108 => Some("status"),
+            109 => Some("disconnect"),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for BgbPacket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.command_name() {
+            Some(name) => write!(f, "{}", name)?,
+            None => write!(f, "unknown({})", self.command)?,
+        }
+        write!(f, " data=0x{:02X} extra1=0x{:02X} extra2=0x{:02X} ts=0x{:08X}",
+            self.data, self.extra1, self.extra2, self.timestamp)
+    }
+}
+
+/// Flags in the `data` byte of a status (108) packet:
+///   bit 0 = running (emulation not stopped)
+///   bit 1 = paused (e.g. BGB's debugger or menu is open)
+///   bit 2 = supports reconnect (peer may drop and reconnect mid-session)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatusFlags {
+    pub running: bool,
+    pub paused: bool,
+    pub supports_reconnect: bool,
+}
+
+impl Default for StatusFlags {
+    /// Running, not paused: what the bridge has always advertised.
+    fn default() -> Self {
+        Self { running: true, paused: false, supports_reconnect: false }
+    }
+}
+
+impl StatusFlags {
+    pub fn from_byte(b: u8) -> Self {
+        Self { running: b & 1 != 0, paused: b & 2 != 0, supports_reconnect: b & 4 != 0 }
+    }
+
+    pub fn to_byte(self) -> u8 {
+        self.running as u8 | (self.paused as u8) << 1 | (self.supports_reconnect as u8) << 2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_names_each_command() {
+        let cases = [
+            (1, "version"),
+            (104, "sync1"),
+            (105, "sync2"),
+            (106, "sync3"),
+            (108, "status"),
+            (109, "disconnect"),
+            (42, "unknown(42)"),
+        ];
+        for (command, name) in cases {
+            let pkt = BgbPacket::new(command, 0x29, 0x81, 0x00, 0x1234);
+            assert_eq!(pkt.to_string(),
+                format!("{} data=0x29 extra1=0x81 extra2=0x00 ts=0x00001234", name));
+        }
+    }
+
+    #[test]
+    fn status_flags_map_to_bits() {
+        assert_eq!(StatusFlags::default().to_byte(), 0x01);
+        let paused = StatusFlags { running: true, paused: true, supports_reconnect: false };
+        assert_eq!(paused.to_byte(), 0x03);
+        let reconnect = StatusFlags { running: false, paused: false, supports_reconnect: true };
+        assert_eq!(reconnect.to_byte(), 0x04);
+        assert_eq!(StatusFlags::from_byte(0x03), paused);
+    }
 }
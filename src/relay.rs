@@ -0,0 +1,103 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::bgb::{BgbClient, SlaveLink};
+
+/// One of the two emulators in relay mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    A,
+    B,
+}
+
+impl Side {
+    pub fn name(self) -> &'static str {
+        match self {
+            Side::A => "a",
+            Side::B => "b",
+        }
+    }
+}
+
+/// A byte pair carried across the virtual link cable.
+#[derive(Debug, Clone)]
+pub enum RelayEvent {
+    /// `master` clocked out `sent`; the other Game Boy answered `received`
+    Transfer { master: Side, sent: u8, received: u8 },
+    /// Relaying a transfer failed on the other side
+    Error { master: Side, message: String },
+}
+
+/// Link-cable proxy between two BGB instances. Whichever Game Boy acts as
+/// master starts a transfer on its own BGB; the relay replays the byte to
+/// the other BGB as our own transfer and hands the answer back.
+pub struct Relay {
+    stop: Arc<AtomicBool>,
+    pub event_rx: mpsc::Receiver<RelayEvent>,
+    /// Returns whether both clients shut down cleanly
+    thread: thread::JoinHandle<bool>,
+}
+
+impl Relay {
+    pub fn start(a: BgbClient, b: BgbClient) -> Result<Self, String> {
+        let slave_a = a.relay_incoming()?;
+        let slave_b = b.relay_incoming()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let (event_tx, event_rx) = mpsc::channel();
+        let thread_stop = stop.clone();
+        let thread = thread::spawn(move || {
+            let sides = [(Side::A, &slave_a, &b), (Side::B, &slave_b, &a)];
+            'relay: while !thread_stop.load(Ordering::Relaxed) {
+                let mut idle = true;
+                for (side, slave, other) in sides {
+                    if let Ok(sent) = slave.incoming.try_recv() {
+                        idle = false;
+                        if !relay_one(side, sent, slave, other, &event_tx) {
+                            break 'relay;
+                        }
+                    }
+                }
+                if idle {
+                    thread::sleep(Duration::from_millis(1));
+                }
+            }
+            // Shut both down even if the first one hangs
+            let a_clean = a.shutdown();
+            b.shutdown() && a_clean
+        });
+        Ok(Self { stop, event_rx, thread })
+    }
+
+    /// Stop relaying and shut down both clients. Returns false if either
+    /// BGB thread did not exit cleanly.
+    pub fn stop(self) -> bool {
+        self.stop.store(true, Ordering::Relaxed);
+        self.thread.join().unwrap_or(false)
+    }
+}
+
+/// Forward one master byte to `other` and answer the master with its reply.
+/// Returns false once nobody is listening for events any more.
+fn relay_one(
+    master: Side,
+    sent: u8,
+    slave: &SlaveLink,
+    other: &BgbClient,
+    event_tx: &mpsc::Sender<RelayEvent>,
+) -> bool {
+    let event = match other.exchange_byte(sent) {
+        Ok(received) => {
+            let _ = slave.answer.send(received);
+            RelayEvent::Transfer { master, sent, received }
+        }
+        Err(message) => {
+            // Unblock the master; a disconnected cable reads as 0xFF
+            let _ = slave.answer.send(0xFF);
+            RelayEvent::Error { master, message }
+        }
+    };
+    event_tx.send(event).is_ok()
+}
@@ -0,0 +1,65 @@
+use serde::{Deserialize, Serialize};
+
+/// GUI preferences persisted in eframe's storage between launches.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub dark_mode: bool,
+    /// Lines kept in the on-screen log before the oldest are dropped
+    pub log_capacity: usize,
+    /// Stop the bridge after this many idle minutes; 0 disables
+    pub idle_timeout_mins: u32,
+    /// End a match after this many minutes; 0 disables
+    pub max_match_mins: u32,
+    /// Longest wait between polls of an idle BGB connection, in ms. Higher
+    /// uses less CPU but reacts later to packets BGB sends unprompted.
+    pub bgb_idle_poll_ms: u64,
+    /// How long BGB gets to answer the connect handshake, in ms
+    pub bgb_handshake_timeout_ms: u64,
+    /// How long BGB gets to answer each exchanged byte, in ms
+    pub bgb_exchange_timeout_ms: u64,
+    /// Seconds of silence before a status keepalive is sent to BGB; 0 disables
+    pub bgb_keepalive_secs: u64,
+    /// Per-session log files to keep; older ones are deleted. 0 keeps all.
+    pub log_files_kept: usize,
+    /// Retry the log file after a write fails instead of giving up on it
+    pub reopen_log: bool,
+    /// Connect to BGB when a game starts rather than when the browser does
+    pub lazy_bgb: bool,
+    /// Seconds a game keeps running after its browser drops, for the
+    /// browser to reconnect and resume it; 0 ends it right away
+    pub reconnect_grace_secs: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            dark_mode: true,
+            log_capacity: 500,
+            idle_timeout_mins: 0,
+            max_match_mins: 0,
+            bgb_idle_poll_ms: 16,
+            bgb_keepalive_secs: 0,
+            bgb_handshake_timeout_ms: 3000,
+            bgb_exchange_timeout_ms: 5000,
+            log_files_kept: 20,
+            reopen_log: false,
+            lazy_bgb: false,
+            reconnect_grace_secs: 0,
+        }
+    }
+}
+
+impl Settings {
+    const KEY: &'static str = "settings";
+
+    pub fn load(storage: Option<&dyn eframe::Storage>) -> Self {
+        storage
+            .and_then(|s| eframe::get_value(s, Self::KEY))
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, Self::KEY, self);
+    }
+}
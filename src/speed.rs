@@ -0,0 +1,48 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Emulation speed multiplier shared between the GUI, the game thread and
+/// the BGB thread. 1.0 is normal speed; higher values mean BGB is fast
+/// forwarding, so link timestamps advance faster and game ticks come sooner.
+#[derive(Debug, Clone)]
+pub struct Speed(Arc<AtomicU32>);
+
+impl Speed {
+    pub const MIN: f32 = 0.25;
+    pub const MAX: f32 = 8.0;
+
+    pub fn new(multiplier: f32) -> Self {
+        let speed = Self(Arc::new(AtomicU32::new(1.0f32.to_bits())));
+        speed.set(multiplier);
+        speed
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    /// Set the multiplier, clamped to `MIN..=MAX`. Non-finite values are ignored.
+    pub fn set(&self, multiplier: f32) {
+        if multiplier.is_finite() {
+            let clamped = multiplier.clamp(Self::MIN, Self::MAX);
+            self.0.store(clamped.to_bits(), Ordering::Relaxed);
+        }
+    }
+
+    /// Scale a real-time interval down by the multiplier.
+    pub fn scale_interval(&self, interval: Duration) -> Duration {
+        interval.div_f32(self.get())
+    }
+
+    /// Scale a BGB timestamp increment up by the multiplier (at least 1).
+    pub fn scale_ticks(&self, ticks: u32) -> u32 {
+        ((ticks as f32 * self.get()).round() as u32).max(1)
+    }
+}
+
+impl Default for Speed {
+    fn default() -> Self {
+        Self::new(1.0)
+    }
+}
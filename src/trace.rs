@@ -0,0 +1,157 @@
+//! Headless `trace` subcommand: play a byte script against BGB and record
+//! the replies, for firmware regression runs in CI.
+//!
+//!     bgb-web-bridge trace <script> <output> [host:port]
+//!
+//! Each script line is a byte to send and optionally the reply expected for
+//! it, in hex (`29 55` or `0x29 0x55`). Blank lines and `#` comments are
+//! skipped. The output has one `sent received` line per step, with
+//! `MISMATCH expected XX` appended where the reply differed.
+
+use std::io::Write;
+
+use crate::bgb::{BgbClient, BgbEndpoint, PollInterval, Timeouts};
+use crate::protocol::StatusFlags;
+use crate::speed::Speed;
+use crate::verbosity::Verbosity;
+
+/// Every reply matched.
+const EXIT_OK: i32 = 0;
+/// At least one reply differed from the script's expectation.
+const EXIT_MISMATCH: i32 = 1;
+/// Bad arguments or script, BGB unreachable, or an exchange or write failed.
+const EXIT_ERROR: i32 = 2;
+
+/// One script line.
+#[derive(Debug, PartialEq, Eq)]
+struct Step {
+    send: u8,
+    expect: Option<u8>,
+}
+
+/// Run the subcommand with the arguments after `trace`. Returns the process
+/// exit code. Messages go to stderr, since the window subsystem build has
+/// no console to speak of anyway.
+pub fn run(args: &[String], default_host: &str, default_port: u16) -> i32 {
+    let (script_path, output_path) = match args {
+        [script, output] | [script, output, _] => (script, output),
+        _ => {
+            eprintln!("usage: bgb-web-bridge trace <script> <output> [host:port]");
+            return EXIT_ERROR;
+        }
+    };
+    let endpoint = match args.get(2) {
+        Some(addr) => match parse_address(addr) {
+            Ok((host, port)) => BgbEndpoint::Tcp { host: host.to_string(), port },
+            Err(e) => {
+                eprintln!("{}", e);
+                return EXIT_ERROR;
+            }
+        },
+        None => BgbEndpoint::Tcp { host: default_host.to_string(), port: default_port },
+    };
+
+    let steps = match std::fs::read_to_string(script_path)
+        .map_err(|e| format!("{}: {}", script_path, e))
+        .and_then(|text| parse_script(&text))
+    {
+        Ok(steps) => steps,
+        Err(e) => {
+            eprintln!("Bad script: {}", e);
+            return EXIT_ERROR;
+        }
+    };
+
+    let bgb = match BgbClient::connect(&endpoint, None, Verbosity::default(), Speed::default(),
+        PollInterval::default(), StatusFlags::default(), Timeouts::default())
+    {
+        Ok(bgb) => bgb,
+        Err(e) => {
+            eprintln!("BGB connect failed: {}", e);
+            return EXIT_ERROR;
+        }
+    };
+
+    let mut output = Vec::new();
+    let mut mismatches = 0;
+    for step in &steps {
+        let received = match bgb.exchange_byte(step.send) {
+            Ok(b) => b,
+            Err(e) => {
+                eprintln!("Exchange of 0x{:02X} failed: {}", step.send, e);
+                bgb.shutdown();
+                return EXIT_ERROR;
+            }
+        };
+        let _ = write!(output, "{:02X} {:02X}", step.send, received);
+        if let Some(expect) = step.expect.filter(|&e| e != received) {
+            mismatches += 1;
+            let _ = write!(output, " MISMATCH expected {:02X}", expect);
+        }
+        output.push(b'\n');
+    }
+    bgb.shutdown();
+
+    if let Err(e) = std::fs::write(output_path, &output) {
+        eprintln!("{}: {}", output_path, e);
+        return EXIT_ERROR;
+    }
+    eprintln!("{} steps, {} mismatches", steps.len(), mismatches);
+    if mismatches > 0 { EXIT_MISMATCH } else { EXIT_OK }
+}
+
+/// Split a `host:port` argument, refusing port 0, which would leave the
+/// connect with nowhere to go.
+fn parse_address(addr: &str) -> Result<(&str, u16), String> {
+    let (host, port) = addr.rsplit_once(':')
+        .and_then(|(host, port)| Some((host, port.parse::<u16>().ok()?)))
+        .ok_or_else(|| format!("Invalid BGB address {} (expected host:port)", addr))?;
+    if port == 0 {
+        return Err(format!("Invalid BGB address {} (port must not be 0)", addr));
+    }
+    Ok((host, port))
+}
+
+fn parse_script(text: &str) -> Result<Vec<Step>, String> {
+    let byte = |s: &str, line: usize| {
+        let digits = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).unwrap_or(s);
+        u8::from_str_radix(digits, 16).map_err(|_| format!("line {}: {} is not a hex byte", line, s))
+    };
+    let mut steps = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut fields = line.split_whitespace();
+        let Some(send) = fields.next() else { continue };
+        let send = byte(send, line_no)?;
+        let expect = fields.next().map(|s| byte(s, line_no)).transpose()?;
+        if fields.next().is_some() {
+            return Err(format!("line {}: expected at most two bytes", line_no));
+        }
+        steps.push(Step { send, expect });
+    }
+    Ok(steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script_lines_take_a_byte_and_an_optional_expectation() {
+        let steps = parse_script("# probe\n0x29 0x55\n\n50  # confirm music\n").unwrap();
+        assert_eq!(steps, [
+            Step { send: 0x29, expect: Some(0x55) },
+            Step { send: 0x50, expect: None },
+        ]);
+        assert_eq!(parse_script("29 55 00").unwrap_err(), "line 1: expected at most two bytes");
+        assert_eq!(parse_script("\nzz").unwrap_err(), "line 2: zz is not a hex byte");
+    }
+
+    #[test]
+    fn addresses_need_a_nonzero_port() {
+        assert_eq!(parse_address("10.0.0.2:8765"), Ok(("10.0.0.2", 8765)));
+        assert!(parse_address("localhost").is_err());
+        assert_eq!(parse_address("localhost:0").unwrap_err(), "Invalid BGB address localhost:0 (port must not be 0)");
+    }
+}
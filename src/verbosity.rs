@@ -0,0 +1,35 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+/// Which categories of verbose log lines are enabled, shared between the
+/// GUI, the game thread and the BGB thread. Each category is one bit; 0 is
+/// plain logging only and `ALL` is the old "verbose on".
+#[derive(Debug, Clone, Default)]
+pub struct Verbosity(Arc<AtomicU8>);
+
+impl Verbosity {
+    /// Packets we send to BGB
+    pub const SEND: u8 = 1 << 0;
+    /// Packets BGB sends us, and waits for them
+    pub const RECV: u8 = 1 << 1;
+    /// Game thread detail: sequences, unrecognized bytes, identification
+    pub const GAME: u8 = 1 << 2;
+    pub const ALL: u8 = Self::SEND | Self::RECV | Self::GAME;
+
+    /// Categories with the names the GUI shows for them.
+    pub const CATEGORIES: [(u8, &'static str); 3] =
+        [(Self::SEND, "Sends"), (Self::RECV, "Receives"), (Self::GAME, "Game")];
+
+    pub fn get(&self) -> u8 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Replace the mask. Bits outside `ALL` are dropped.
+    pub fn set(&self, mask: u8) {
+        self.0.store(mask & Self::ALL, Ordering::Relaxed);
+    }
+
+    pub fn enabled(&self, category: u8) -> bool {
+        self.get() & category != 0
+    }
+}
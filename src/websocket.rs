@@ -1,44 +1,214 @@
-use std::net::TcpListener;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
 use std::sync::mpsc;
-use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use tungstenite::protocol::Message;
-use tungstenite::accept;
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::http::{HeaderValue, StatusCode};
+use tungstenite::protocol::frame::coding::CloseCode;
+use tungstenite::protocol::{CloseFrame, Message, WebSocketConfig};
+use tungstenite::{accept_hdr_with_config, accept_with_config, WebSocket};
 
-use crate::bgb::BgbClient;
-use crate::game::{GameThread, GameCommand, GameEvent};
+use crate::bgb::{BgbClient, BgbEndpoint, BgbVersion, Direction, LinkStats, PollInterval, SerialClock, Timeouts, LATENCY_BUCKETS_MS};
+use crate::bridge::Bridge;
+use crate::clock::SystemClock;
+use crate::link::{LazyLink, Link, SimLink};
+use crate::protocol::StatusFlags;
+use crate::relay::{Relay, RelayEvent, Side};
+use crate::speed::Speed;
+use crate::verbosity::Verbosity;
+use crate::metrics::{self, Metrics};
+use crate::game::{self, ConfigUpdate, GameThread, LineMap, LinkFactory, GameCommand, GameConfig, GameEvent, Probe, QueuePolicy};
+
+/// Address the WebSocket server binds to (all interfaces).
+pub const WS_BIND_HOST: &str = "0.0.0.0";
+
+/// Largest browser message we accept. Commands are small JSON objects; the
+/// biggest is start_game with its garbage/tiles arrays, well under this.
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+/// Version of the JSON message schema, sent as `"v"` in every message.
+/// Bump it when existing messages change incompatibly; additions are
+/// advertised through the hello message's capabilities instead.
+const PROTOCOL_VERSION: u32 = 1;
+
+/// Commands `parse_browser_command` accepts, advertised in the hello message.
+const COMMANDS: &[&str] = &[
+    "set_game", "set_music", "confirm_music", "end_match", "selftest", "get_height", "get_config", "query_status",
+    "list_games", "get_packets", "latency_histogram", "reset_link", "reconnect_bgb", "snapshot_state", "restore_state",
+    "force_in_game", "set_clock", "emu_pause", "final_screen", "start_game", "send_seq", "timed_seq", "set_height",
+    "queue_command", "queue_lines", "set_line_map", "loop_override", "set_queue_policy", "set_speed", "set_config",
+    "set_heartbeat", "set_log_mask",
+];
+
+/// Commands the WebSocket thread handles itself rather than passing to the
+/// game thread, also advertised in the hello message.
+const CONNECTION_COMMANDS: &[&str] = &["identify"];
+
+/// Events the bridge may send, advertised in the hello message.
+const EVENTS: &[&str] = &[
+    "hello", "ack", "nack", "busy", "connected",
+    "game_started", "raw_byte", "loop_override", "music_set", "height", "lines", "win", "lose",
+    "screen_filled", "error", "warning", "idle_timeout", "session_timeout", "desync", "timing", "slow_link", "unhandled", "height_echo", "latency_histogram", "config", "snapshot", "status", "command_sent", "emu_paused", "packets", "games", "match_ended",
+    "probe_diagnostic", "selftest", "seq_response", "seq_progress", "seq_done", "heartbeat", "relay", "relay_error", "bgb_disconnected", "bgb_connected",
+];
+
+/// Outgoing bytes tungstenite may buffer for a slow browser before writes fail.
+const MAX_WRITE_BUFFER: usize = 256 * 1024;
+/// Events waiting in one pass beyond which droppable ones are shed.
+const EVENT_BACKLOG_LIMIT: usize = 64;
 
 /// Messages sent from the WebSocket thread back to the GUI.
 pub enum WsEvent {
     Log(String),
     BrowserConnected,
+    /// The browser named its front-end with `identify`
+    BrowserIdentified(ClientInfo),
     BrowserDisconnected,
-    BgbConnected,
+    BgbConnected(BgbVersion),
     BgbDisconnected,
-    Stopped,
+    /// A byte crossed the link, for the GUI's byte trace. Bursts beyond
+    /// what the link stats hold between polls are dropped.
+    ByteTrace { dir: Direction, byte: u8 },
+    /// A match started on the Game Boy
+    MatchStarted,
+    /// The Game Boy reported the end of a match (true = we won)
+    MatchEnded { won: bool },
+    /// The game left its match, e.g. on end_match, a BGB reconnect or the
+    /// match duration limit.
+    /// Without a preceding `MatchEnded`, the match had no result.
+    MatchAborted,
+    /// The server stopped. `last_disconnect` is how the most recent browser
+    /// session ended, if there was one; `exchanges` counts the whole run.
+    Stopped {
+        reason: StopReason,
+        last_disconnect: Option<StopReason>,
+        exchanges: u64,
+    },
+}
+
+/// Which front-end a browser connection is, from its `identify` command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub name: String,
+    pub version: Option<String>,
+}
+
+impl std::fmt::Display for ClientInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.version {
+            Some(version) => write!(f, "{} v{}", self.name, version),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// Why the server or a browser session ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    UserStop,
+    BgbLost,
+    BrowserLost,
+    /// The browser closed the WebSocket itself
+    BrowserClosed,
+    BindFailed,
+    /// The game thread hit its idle timeout
+    Idle,
+}
+
+impl std::fmt::Display for StopReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            StopReason::UserStop => "user stop",
+            StopReason::BgbLost => "BGB lost",
+            StopReason::BrowserLost => "browser lost",
+            StopReason::BrowserClosed => "browser closed",
+            StopReason::BindFailed => "bind failed",
+            StopReason::Idle => "idle",
+        })
+    }
 }
 
 /// Messages sent from the GUI to the WebSocket thread.
 pub enum WsCommand {
     Stop,
+    /// Probe the Game Boy once and log the result
+    SelfTest,
+    /// Replace the session's BGB link, as the `reconnect_bgb` command does
+    ReconnectBgb,
+}
+
+/// Sends `WsEvent`s to the GUI. BGB connects and disconnects are noticed
+/// on several paths (the link thread, lazy links, reconnect_bgb, session
+/// teardown), so this is where they are reconciled: each is passed on only
+/// when it changes what the GUI was last told.
+#[derive(Clone)]
+struct EventSender {
+    tx: mpsc::Sender<WsEvent>,
+    bgb_up: Arc<AtomicBool>,
+}
+
+impl EventSender {
+    fn new(tx: mpsc::Sender<WsEvent>) -> Self {
+        Self { tx, bgb_up: Arc::default() }
+    }
+
+    fn send(&self, event: WsEvent) -> Result<(), mpsc::SendError<WsEvent>> {
+        match event {
+            WsEvent::BgbConnected(_) if self.bgb_up.swap(true, Ordering::Relaxed) => Ok(()),
+            WsEvent::BgbDisconnected if !self.bgb_up.swap(false, Ordering::Relaxed) => Ok(()),
+            event => self.tx.send(event),
+        }
+    }
+}
+
+/// Settings for one run of the server, fixed when the GUI clicks Start.
+#[derive(Clone)]
+pub struct ServerConfig {
+    pub ws_port: u16,
+    pub bgb: BgbEndpoint,
+    /// Relay mode: link `bgb` to this second BGB instead of running games
+    pub relay: Option<BgbEndpoint>,
+    /// Serve Prometheus-style metrics over HTTP on this port when set
+    pub metrics_port: Option<u16>,
+    pub verbose: Verbosity,
+    pub speed: Speed,
+    /// Starting configuration for each game thread
+    pub game: GameConfig,
+    /// Use a simulated link instead of connecting to BGB
+    pub simulate: bool,
+    /// Connect to BGB only while a game needs the link
+    pub lazy_bgb: bool,
+    /// Idle polling of the BGB connection (CPU vs responsiveness)
+    pub bgb_poll: PollInterval,
+    /// Status flags advertised to BGB right after the handshake
+    pub bgb_status: StatusFlags,
+    /// How long to wait for BGB's handshake and exchange replies
+    pub bgb_timeouts: Timeouts,
+    /// Keep a JSON game running this long after its browser drops, so a
+    /// browser reconnecting with the session token can resume it
+    pub reconnect_grace: Option<Duration>,
 }
 
 /// Run the WebSocket server. Blocks until stopped via command channel.
 pub fn run(
-    ws_port: u16,
-    bgb_host: String,
-    bgb_port: u16,
+    config: ServerConfig,
     event_tx: mpsc::Sender<WsEvent>,
     cmd_rx: mpsc::Receiver<WsCommand>,
-    verbose: Arc<AtomicBool>,
 ) {
-    let addr = format!("0.0.0.0:{}", ws_port);
+    let event_tx = EventSender::new(event_tx);
+    let addr = format!("{}:{}", WS_BIND_HOST, config.ws_port);
     let listener = match TcpListener::bind(&addr) {
         Ok(l) => l,
         Err(e) => {
             let _ = event_tx.send(WsEvent::Log(format!("Failed to bind {}: {}", addr, e)));
-            let _ = event_tx.send(WsEvent::Stopped);
+            let _ = event_tx.send(WsEvent::Stopped {
+                reason: StopReason::BindFailed,
+                last_disconnect: None,
+                exchanges: 0,
+            });
             return;
         }
     };
@@ -48,19 +218,71 @@ pub fn run(
 
     let _ = event_tx.send(WsEvent::Log(format!("WebSocket server listening on {}", addr)));
 
+    let started_at = Instant::now();
+    let metrics = Arc::new(Metrics::default());
+    let metrics_stop = Arc::new(AtomicBool::new(false));
+    if let Some(port) = config.metrics_port {
+        let metrics = metrics.clone();
+        let stop = metrics_stop.clone();
+        let log_tx = log_forwarder(&event_tx);
+        std::thread::spawn(move || metrics::serve(port, metrics, stop, log_tx));
+    }
+
+    let mut stop_reason = StopReason::UserStop;
+    let mut last_disconnect = None;
+    let mut exchanges: u64 = 0;
+    // A game whose browser dropped, waiting for it to reconnect
+    let mut parked: Option<Parked> = None;
+    // A self-test started while no browser was connected
+    let mut self_test: Option<std::thread::JoinHandle<()>> = None;
+
     loop {
-        // Check for stop command
-        if let Ok(WsCommand::Stop) = cmd_rx.try_recv() {
-            let _ = event_tx.send(WsEvent::Log("Stopping server...".into()));
-            break;
+        // Check for GUI commands
+        match cmd_rx.try_recv() {
+            Ok(WsCommand::Stop) => {
+                let _ = event_tx.send(WsEvent::Log("Stopping server...".into()));
+                break;
+            }
+            Ok(WsCommand::SelfTest) if parked.is_some() => {
+                let _ = event_tx.send(WsEvent::Log("Self-test unavailable while a game waits for its browser".into()));
+            }
+            Ok(WsCommand::SelfTest) if self_test.as_ref().is_some_and(|t| !t.is_finished()) => {
+                let _ = event_tx.send(WsEvent::Log("Self-test already running".into()));
+            }
+            Ok(WsCommand::SelfTest) => {
+                // No browser session, so open a short-lived BGB link just for
+                // the test. Off this thread, so a BGB that never answers can't
+                // hold up Stop or new browsers.
+                let config = config.clone();
+                let event_tx = event_tx.clone();
+                self_test = Some(std::thread::spawn(move || {
+                    let msg = match open_link(&config, None) {
+                        Ok((link, _)) => game::self_test(&*link, Probe::default()).to_string(),
+                        Err(e) => format!("Self-test failed: {}", e),
+                    };
+                    let _ = event_tx.send(WsEvent::Log(msg));
+                }));
+            }
+            Ok(WsCommand::ReconnectBgb) if parked.is_some() => {
+                let _ = event_tx.send(WsEvent::Log("Reconnect unavailable while a game waits for its browser".into()));
+            }
+            Ok(WsCommand::ReconnectBgb) => {
+                let _ = event_tx.send(WsEvent::Log("No session to reconnect; BGB is linked when a browser connects".into()));
+            }
+            Err(_) => {}
+        }
+
+        if let Some(p) = &parked {
+            p.forward_events(&metrics, &event_tx);
+        }
+        if parked.as_ref().is_some_and(|p| Instant::now() >= p.expires) {
+            let _ = event_tx.send(WsEvent::Log("Browser did not reconnect in time, ending its game".into()));
+            exchanges += parked.take().unwrap().end(&event_tx);
         }
 
         // Try to accept a new connection
-        let stream = match listener.accept() {
-            Ok((stream, peer)) => {
-                let _ = event_tx.send(WsEvent::Log(format!("Browser connected from {}", peer)));
-                stream
-            }
+        let (mut stream, peer) = match listener.accept() {
+            Ok(accepted) => accepted,
             Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
                 std::thread::sleep(std::time::Duration::from_millis(50));
                 continue;
@@ -75,7 +297,46 @@ pub fn run(
         // Switch to blocking for the WebSocket connection
         stream.set_nonblocking(false).ok();
 
-        let websocket = match accept(stream) {
+        // Someone opened our address in a web browser: show that we're here
+        if is_plain_http_get(&stream) {
+            let page = status_page(metrics.bgb_connected(), started_at.elapsed(), config.ws_port);
+            let mut request = [0u8; 2048];
+            let _ = stream.read(&mut request);
+            let _ = write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                page.len(),
+                page
+            );
+            let _ = event_tx.send(WsEvent::Log(format!("Served the status page to {}", peer)));
+            continue;
+        }
+        let _ = event_tx.send(WsEvent::Log(format!("Browser connected from {}", peer)));
+
+        // A reconnecting browser asks for its parked game in the URL query
+        let mut requested_token = None;
+        let mut subprotocol = None;
+        // The error type is tungstenite's, fixed by its callback signature
+        #[allow(clippy::result_large_err)]
+        let callback = |request: &Request, mut response: Response| {
+            requested_token = session_token(request.uri().query());
+            let offered = request.headers().get("Sec-WebSocket-Protocol").and_then(|v| v.to_str().ok());
+            match choose_subprotocol(offered) {
+                Ok(chosen) => {
+                    if let Some(chosen) = chosen {
+                        response.headers_mut().insert("Sec-WebSocket-Protocol", HeaderValue::from_static(chosen.name()));
+                    }
+                    subprotocol = chosen;
+                    Ok(response)
+                }
+                Err(e) => {
+                    let mut rejection = ErrorResponse::new(Some(e));
+                    *rejection.status_mut() = StatusCode::BAD_REQUEST;
+                    Err(rejection)
+                }
+            }
+        };
+        let websocket = match accept_hdr_with_config(stream, callback, Some(ws_config())) {
             Ok(ws) => ws,
             Err(e) => {
                 let _ = event_tx.send(WsEvent::Log(format!("WebSocket handshake failed: {}", e)));
@@ -84,98 +345,599 @@ pub fn run(
         };
 
         let _ = event_tx.send(WsEvent::BrowserConnected);
+        metrics.browser_connected.store(true, Ordering::Relaxed);
 
-        handle_connection(websocket, &bgb_host, bgb_port, &event_tx, &cmd_rx, &verbose);
+        let opening = match parked.take() {
+            Some(p) if requested_token.as_deref() == Some(p.token.as_str()) => Opening::Resume(p),
+            Some(p) => {
+                // The parked game holds the BGB link, so it can't outlive a new session
+                let _ = event_tx.send(WsEvent::Log("New browser session, ending the game kept for reconnect".into()));
+                exchanges += p.end(&event_tx);
+                Opening::New(subprotocol)
+            }
+            None => Opening::New(subprotocol),
+        };
+
+        let (reason, session_exchanges, kept) =
+            handle_connection(websocket, &listener, &config, &metrics, &event_tx, &cmd_rx, opening);
+        exchanges += session_exchanges;
+        last_disconnect = Some(reason);
+        parked = kept;
 
         let _ = event_tx.send(WsEvent::BrowserDisconnected);
+        metrics.browser_connected.store(false, Ordering::Relaxed);
+
+        if matches!(reason, StopReason::UserStop | StopReason::Idle) {
+            stop_reason = reason;
+            break;
+        }
     }
 
-    let _ = event_tx.send(WsEvent::Stopped);
+    if let Some(p) = parked {
+        exchanges += p.end(&event_tx);
+    }
+
+    metrics_stop.store(true, Ordering::Relaxed);
+    let _ = event_tx.send(WsEvent::Stopped {
+        reason: stop_reason,
+        last_disconnect,
+        exchanges,
+    });
 }
 
-fn handle_connection(
-    mut websocket: tungstenite::WebSocket<std::net::TcpStream>,
-    bgb_host: &str,
-    bgb_port: u16,
-    event_tx: &mpsc::Sender<WsEvent>,
-    cmd_rx: &mpsc::Receiver<WsCommand>,
-    verbose: &Arc<AtomicBool>,
-) {
-    // Create a log sender that forwards BGB thread logs to the GUI
-    let bgb_log_tx = {
-        let tx = event_tx.clone();
-        let (log_tx, log_rx) = mpsc::channel::<String>();
-        std::thread::spawn(move || {
-            while let Ok(msg) = log_rx.recv() {
-                let _ = tx.send(WsEvent::Log(msg));
-            }
+/// How long the accept loop waits for a request head to arrive in full.
+const HEAD_PEEK_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Peek at a new connection's request head and report whether it is a
+/// plain HTTP GET rather than a WebSocket upgrade. Nothing is consumed, so
+/// an upgrade proceeds to the handshake untouched. Runs on the accept
+/// loop, so a client that trickles its head in is given `HEAD_PEEK_TIMEOUT`.
+fn is_plain_http_get(stream: &TcpStream) -> bool {
+    let deadline = Instant::now() + HEAD_PEEK_TIMEOUT;
+    let _ = stream.set_read_timeout(Some(HEAD_PEEK_TIMEOUT));
+    let mut buf = [0u8; 2048];
+    let mut len = 0;
+    // The head may arrive in pieces; wait for all of it
+    while let Ok(n) = stream.peek(&mut buf) {
+        len = n;
+        if n == 0 || head_complete(&buf[..n]) || n == buf.len() || Instant::now() >= deadline {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    let _ = stream.set_read_timeout(None);
+    is_plain_get(&buf[..len])
+}
+
+fn head_complete(head: &[u8]) -> bool {
+    head.windows(4).any(|w| w == b"\r\n\r\n")
+}
+
+/// Whether `head` is a whole GET request head without a WebSocket upgrade.
+/// One cut short, e.g. by filling the peek buffer, could still be hiding
+/// an upgrade header, so it counts as not plain.
+fn is_plain_get(head: &[u8]) -> bool {
+    if !head_complete(head) {
+        return false;
+    }
+    let head = String::from_utf8_lossy(head);
+    let upgrade = head.lines().any(|line| {
+        line.split_once(':').is_some_and(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("upgrade") && value.to_ascii_lowercase().contains("websocket")
+        })
+    });
+    head.starts_with("GET ") && !upgrade
+}
+
+/// The page a plain HTTP GET to the WebSocket port gets.
+fn status_page(bgb_connected: bool, uptime: Duration, ws_port: u16) -> String {
+    let secs = uptime.as_secs();
+    format!(
+        "<!DOCTYPE html>\n<html><head><title>BGB Web Bridge</title></head><body>\n\
+         <h1>BGB Web Bridge is running</h1>\n\
+         <p>BGB: {}</p>\n<p>Uptime: {}h {:02}m {:02}s</p>\n\
+         <p>This port speaks WebSocket. Point your game at <code>ws://localhost:{}/</code>.</p>\n\
+         </body></html>\n",
+        if bgb_connected { "connected" } else { "not connected" },
+        secs / 3600, secs / 60 % 60, secs % 60,
+        ws_port
+    )
+}
+
+/// Connect to BGB, or create a simulated link in simulation mode. Returns
+/// the BGB version alongside a real connection.
+fn open_link(
+    config: &ServerConfig,
+    log_tx: Option<mpsc::Sender<String>>,
+) -> Result<(Box<dyn Link>, Option<BgbVersion>), String> {
+    if config.simulate {
+        return Ok((Box::new(SimLink::new(log_tx)), None));
+    }
+    let bgb = BgbClient::connect(&config.bgb, log_tx, config.verbose.clone(), config.speed.clone(),
+        config.bgb_poll, config.bgb_status, config.bgb_timeouts)?;
+    let version = bgb.version();
+    Ok((Box::new(bgb), Some(version)))
+}
+
+/// A BGB link that connects on first use and disconnects after each match,
+/// reporting both to the GUI.
+fn lazy_link(config: &ServerConfig, log_tx: mpsc::Sender<String>, event_tx: &EventSender) -> Box<dyn Link> {
+    let endpoint = config.bgb.clone();
+    let verbose = config.verbose.clone();
+    let speed = config.speed.clone();
+    let (poll, status, timeouts) = (config.bgb_poll, config.bgb_status, config.bgb_timeouts);
+    let connect = move || {
+        BgbClient::connect(&endpoint, Some(log_tx.clone()), verbose.clone(), speed.clone(), poll, status, timeouts)
+    };
+    let event_tx = event_tx.clone();
+    let notify = move |version| {
+        let _ = event_tx.send(match version {
+            Some(version) => WsEvent::BgbConnected(version),
+            None => WsEvent::BgbDisconnected,
         });
-        log_tx
     };
+    Box::new(LazyLink::new(connect, notify))
+}
 
-    // Connect to BGB
-    let bgb = match BgbClient::connect(bgb_host, bgb_port, Some(bgb_log_tx), verbose.clone()) {
-        Ok(b) => {
-            let _ = event_tx.send(WsEvent::BgbConnected);
-            let _ = event_tx.send(WsEvent::Log("Connected to BGB".into()));
-            b
+/// Create a log sender whose messages are forwarded to the GUI.
+fn log_forwarder(event_tx: &EventSender) -> mpsc::Sender<String> {
+    let tx = event_tx.clone();
+    let (log_tx, log_rx) = mpsc::channel::<String>();
+    std::thread::spawn(move || {
+        while let Ok(msg) = log_rx.recv() {
+            let _ = tx.send(WsEvent::Log(msg));
         }
+    });
+    log_tx
+}
+
+/// Limits applied to every browser connection.
+///
+/// Connections are never compressed: tungstenite 0.24 has no
+/// permessage-deflate support and rejects frames with RSV1 set. It leaves the
+/// extension out of the handshake response, so browsers that offer it fall
+/// back to uncompressed frames.
+fn ws_config() -> WebSocketConfig {
+    WebSocketConfig {
+        max_message_size: Some(MAX_MESSAGE_SIZE),
+        max_frame_size: Some(MAX_MESSAGE_SIZE),
+        max_write_buffer_size: MAX_WRITE_BUFFER,
+        ..Default::default()
+    }
+}
+
+/// True for IO errors that mean "try again later" rather than a dead socket.
+fn is_timeout(e: &std::io::Error) -> bool {
+    matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut)
+}
+
+/// Drop events a lagging browser can do without: heartbeats, raw bytes and
+/// all but the newest height. Match results, errors and the rest are kept.
+/// Returns how many were dropped.
+fn shed_events(events: &mut Vec<GameEvent>) -> usize {
+    let before = events.len();
+    let last_height = events.iter().rposition(|e| matches!(e, GameEvent::Height(_)));
+    let mut index = 0;
+    events.retain(|event| {
+        let keep = match event {
+            GameEvent::Height(_) => Some(index) == last_height,
+            _ => !is_sheddable(event),
+        };
+        index += 1;
+        keep
+    });
+    before - events.len()
+}
+
+/// Events a lagging browser can do without. Heights are only sheddable
+/// while a newer one follows, which `shed_events` checks itself.
+fn is_sheddable(event: &GameEvent) -> bool {
+    matches!(event, GameEvent::Heartbeat { .. } | GameEvent::RawByte { .. } | GameEvent::Unhandled(_)
+        | GameEvent::TimedSequenceProgress { .. })
+}
+
+/// Write one event frame to the browser. Returns true if the socket is
+/// backed up. A frame refused for a full write buffer is held back when
+/// `keep` is set, to go out through `drain_held`; while any are held, new
+/// frames queue behind them so the browser still sees them in order.
+fn write_event<S: Read + Write>(
+    websocket: &mut WebSocket<S>,
+    held: &mut VecDeque<Message>,
+    msg: Message,
+    keep: bool,
+) -> Result<bool, String> {
+    if !held.is_empty() {
+        if keep {
+            held.push_back(msg);
+        }
+        return Ok(true);
+    }
+    let written = match websocket.write(msg) {
+        Ok(()) => websocket.flush(),
+        Err(e) => Err(e),
+    };
+    match written {
+        Ok(()) => Ok(false),
+        // The frame stays buffered until a flush goes through
+        Err(tungstenite::Error::Io(ref e)) if is_timeout(e) => Ok(true),
+        Err(tungstenite::Error::WriteBufferFull(msg)) => {
+            if keep {
+                held.push_back(msg);
+            }
+            Ok(true)
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Flush the browser socket and write out held frames in order. Returns
+/// true once everything has gone through.
+fn drain_held<S: Read + Write>(websocket: &mut WebSocket<S>, held: &mut VecDeque<Message>) -> bool {
+    if websocket.flush().is_err() {
+        return false;
+    }
+    while let Some(msg) = held.pop_front() {
+        match websocket.write(msg) {
+            Ok(()) => {}
+            Err(tungstenite::Error::WriteBufferFull(msg)) => {
+                held.push_front(msg);
+                return false;
+            }
+            // Buffered, or the socket is dead and the next read says so
+            Err(_) => return false,
+        }
+    }
+    websocket.flush().is_ok()
+}
+
+/// Accept any browser that connects while a session is active and turn it
+/// away with a `busy` event. Only one session may drive BGB at a time;
+/// two would interleave sync packets into the same emulator.
+fn reject_busy(listener: &TcpListener, event_tx: &EventSender) {
+    while let Ok((stream, peer)) = listener.accept() {
+        let _ = event_tx.send(WsEvent::Log(format!("Rejected browser from {}: already in use", peer)));
+        // The handshake blocks, keep it off the session thread
+        std::thread::spawn(move || {
+            stream.set_nonblocking(false).ok();
+            let _ = stream.set_read_timeout(Some(std::time::Duration::from_secs(2)));
+            if let Ok(mut websocket) = accept_with_config(stream, Some(ws_config())) {
+                let busy = versioned(serde_json::json!({
+                    "event": "busy",
+                    "message": "another browser is already connected to this bridge",
+                }));
+                let _ = send_text(&mut websocket, busy);
+                let _ = websocket.close(Some(CloseFrame {
+                    code: CloseCode::Again,
+                    reason: "busy".into(),
+                }));
+                let _ = websocket.flush();
+            }
+        });
+    }
+}
+
+/// Relay mode: proxy the link cable between two BGB instances while the
+/// browser watches the transfers. Browser commands are refused.
+fn handle_relay(
+    mut websocket: tungstenite::WebSocket<std::net::TcpStream>,
+    listener: &TcpListener,
+    second: &BgbEndpoint,
+    config: &ServerConfig,
+    event_tx: &EventSender,
+    cmd_rx: &mpsc::Receiver<WsCommand>,
+) -> (StopReason, u64) {
+    let connect = |endpoint: &BgbEndpoint, side: Side| {
+        BgbClient::connect(endpoint, Some(log_forwarder(event_tx)), config.verbose.clone(),
+            config.speed.clone(), config.bgb_poll, config.bgb_status, config.bgb_timeouts)
+            .map_err(|e| format!("BGB {} ({}) connect failed: {}", side.name(), endpoint, e))
+    };
+    let relay = connect(&config.bgb, Side::A)
+        .and_then(|a| Ok((a, connect(second, Side::B)?)))
+        .and_then(|(a, b)| {
+            let _ = event_tx.send(WsEvent::BgbConnected(a.version()));
+            let stats = [a.stats(), b.stats()];
+            Ok((Relay::start(a, b)?, stats))
+        });
+    let (relay, stats) = match relay {
+        Ok(r) => r,
         Err(e) => {
-            let _ = event_tx.send(WsEvent::Log(format!("BGB connect failed: {}", e)));
+            let _ = event_tx.send(WsEvent::Log(e));
             let _ = event_tx.send(WsEvent::BgbDisconnected);
             let _ = websocket.close(None);
-            return;
+            return (StopReason::BgbLost, 0);
         }
     };
+    let _ = event_tx.send(WsEvent::Log(format!("Relaying link between {} and {}", config.bgb, second)));
 
-    // Create channels for game thread communication
-    let (game_cmd_tx, game_cmd_rx) = mpsc::channel::<GameCommand>();
-    let (game_event_tx, game_event_rx) = mpsc::channel::<GameEvent>();
+    let _ = websocket.get_ref().set_read_timeout(Some(std::time::Duration::from_millis(50)));
+    let mut reason = StopReason::BrowserLost;
 
-    // Spawn the game thread
-    let game_thread = std::thread::spawn(move || {
-        let mut game = GameThread::new(bgb, game_cmd_rx, game_event_tx);
-        game.run();
-    });
+    loop {
+        match cmd_rx.try_recv() {
+            Ok(WsCommand::Stop) => {
+                let _ = websocket.close(None);
+                reason = StopReason::UserStop;
+                break;
+            }
+            Ok(WsCommand::SelfTest) => {
+                let _ = event_tx.send(WsEvent::Log("Self-test unavailable in relay mode".into()));
+            }
+            Ok(WsCommand::ReconnectBgb) => {
+                let _ = event_tx.send(WsEvent::Log("Reconnect unavailable in relay mode".into()));
+            }
+            Err(_) => {}
+        }
+
+        reject_busy(listener, event_tx);
+
+        if stats.iter().any(|s| !s.connected.load(Ordering::Relaxed)) {
+            let _ = event_tx.send(WsEvent::Log("A relayed BGB disconnected".into()));
+            let _ = websocket.close(None);
+            reason = StopReason::BgbLost;
+            break;
+        }
+
+        let mut write_failed = false;
+        for event in relay.event_rx.try_iter() {
+            let json = match event {
+                RelayEvent::Transfer { master, sent, received } => versioned(serde_json::json!({
+                    "event": "relay",
+                    "master": master.name(),
+                    "sent": sent,
+                    "received": received,
+                })),
+                RelayEvent::Error { master, message } => {
+                    let _ = event_tx.send(WsEvent::Log(format!("Relay from {} failed: {}", master.name(), message)));
+                    versioned(serde_json::json!({
+                        "event": "relay_error",
+                        "master": master.name(),
+                        "message": message,
+                    }))
+                }
+            };
+            if send_text(&mut websocket, json).is_err() {
+                write_failed = true;
+                break;
+            }
+        }
+        if write_failed {
+            break;
+        }
+
+        match websocket.read() {
+            Err(tungstenite::Error::Io(ref e)) if is_timeout(e) => {}
+            Ok(Message::Text(text)) => {
+                let nack = nack_json(&command_name(&text), "relay mode: commands are not accepted");
+                if send_text(&mut websocket, nack).is_err() {
+                    break;
+                }
+            }
+            Ok(Message::Close(_)) => {
+                let _ = event_tx.send(WsEvent::Log("Browser closed the connection".into()));
+                reason = StopReason::BrowserClosed;
+                break;
+            }
+            Err(_) => {
+                let _ = event_tx.send(WsEvent::Log("Browser disconnected".into()));
+                break;
+            }
+            Ok(_) => {}
+        }
+    }
+
+    if !relay.stop() {
+        let _ = event_tx.send(WsEvent::Log("BGB thread did not exit cleanly".into()));
+    }
+    let _ = event_tx.send(WsEvent::BgbDisconnected);
+    let exchanges = stats.iter().map(|s| s.exchanges.load(Ordering::Relaxed)).sum();
+    (reason, exchanges)
+}
+
+fn handle_connection(
+    mut websocket: tungstenite::WebSocket<std::net::TcpStream>,
+    listener: &TcpListener,
+    config: &ServerConfig,
+    metrics: &Metrics,
+    event_tx: &EventSender,
+    cmd_rx: &mpsc::Receiver<WsCommand>,
+    opening: Opening,
+) -> (StopReason, u64, Option<Parked>) {
+    // Games can only be resumed when the browser knows their token
+    let token = match &opening {
+        Opening::Resume(parked) => Some(parked.token.clone()),
+        Opening::New(_) if config.relay.is_none() => config.reconnect_grace.map(|_| new_token()),
+        Opening::New(_) => None,
+    };
+
+    // Announce the schema before anything else so the browser can adapt
+    if let Err(e) = send_text(&mut websocket, hello_json(token.as_deref())) {
+        let _ = event_tx.send(WsEvent::Log(format!("WebSocket write error: {}", e)));
+        return match opening {
+            Opening::Resume(parked) => (StopReason::BrowserLost, 0, Some(parked)),
+            Opening::New(_) => (StopReason::BrowserLost, 0, None),
+        };
+    }
+
+    if let Some(second) = &config.relay {
+        let (reason, exchanges) = handle_relay(websocket, listener, second, config, event_tx, cmd_rx);
+        return (reason, exchanges, None);
+    }
+
+    // Set until the resumed game answers the status query below
+    let mut resuming = matches!(opening, Opening::Resume(_));
+    let (mut session, mut stats) = match opening {
+        Opening::Resume(parked) => {
+            let _ = event_tx.send(WsEvent::Log("Browser reconnected, resuming its game".into()));
+            // Tell the browser where the game got to while it was away
+            let _ = parked.game.cmd_tx.send(GameCommand::QueryStatus);
+            (Session::Json(parked.game), parked.stats)
+        }
+        Opening::New(subprotocol) => match open_session(config, event_tx) {
+            Some((session, stats)) => {
+                let session = match subprotocol {
+                    Some(Subprotocol::Json) => {
+                        let _ = event_tx.send(WsEvent::Log("Browser negotiated JSON mode".into()));
+                        session.into_json(config, event_tx)
+                    }
+                    Some(Subprotocol::Binary) => {
+                        let _ = event_tx.send(WsEvent::Log("Browser negotiated binary mode".into()));
+                        session.into_binary()
+                    }
+                    None => session,
+                };
+                (session, stats)
+            }
+            None => {
+                let _ = websocket.close(None);
+                return (StopReason::BgbLost, 0, None);
+            }
+        },
+    };
+    metrics.attach_link(stats.clone());
 
     // Set a read timeout so we can periodically check for stop commands and game events
     let _ = websocket.get_ref().set_read_timeout(Some(std::time::Duration::from_millis(50)));
 
+    // Bound how long a write can stall on a browser that stopped reading
+    let _ = websocket.get_ref().set_write_timeout(Some(std::time::Duration::from_millis(200)));
+
+    let mut reason = StopReason::BrowserLost;
+    let mut bgb_lost = false;
+    // Exchanges over links replaced by reconnect_bgb
+    let mut retired_exchanges: u64 = 0;
+    // Set while writes to the browser are backing up
+    let mut congested = false;
+    // Frames that must reach the browser but didn't fit its write buffer
+    let mut held: VecDeque<Message> = VecDeque::new();
+
     loop {
-        // Check for stop command from GUI
-        if let Ok(WsCommand::Stop) = cmd_rx.try_recv() {
-            let _ = game_cmd_tx.send(GameCommand::Stop);
-            let _ = websocket.close(None);
-            break;
+        // Check for GUI commands
+        match cmd_rx.try_recv() {
+            Ok(WsCommand::Stop) => {
+                let _ = websocket.close(None);
+                reason = StopReason::UserStop;
+                break;
+            }
+            Ok(WsCommand::SelfTest) => match &session {
+                Session::Pending(bgb) => {
+                    let _ = event_tx.send(WsEvent::Log(game::self_test(&**bgb, Probe::default()).to_string()));
+                }
+                Session::Json(game) => {
+                    let _ = game.cmd_tx.send(GameCommand::SelfTest);
+                }
+                Session::Binary(_) => {
+                    let _ = event_tx.send(WsEvent::Log("Self-test unavailable while a binary client is connected".into()));
+                }
+            },
+            Ok(WsCommand::ReconnectBgb) => match &session {
+                Session::Json(game) => {
+                    let _ = game.cmd_tx.send(GameCommand::ReconnectBgb);
+                }
+                Session::Pending(_) => {
+                    let _ = event_tx.send(WsEvent::Log(
+                        "Reconnect unavailable until the browser sends a JSON message or uses the bgb-json subprotocol".into()));
+                }
+                Session::Binary(_) => {
+                    let _ = event_tx.send(WsEvent::Log("Reconnect unavailable while a binary client is connected".into()));
+                }
+            },
+            Err(_) => {}
+        }
+
+        reject_busy(listener, event_tx);
+
+        // Notice the BGB link dropping; the browser stays connected. A lazy
+        // link reports its own connects and disconnects.
+        if !config.lazy_bgb && !bgb_lost && !stats.connected.load(Ordering::Relaxed) {
+            bgb_lost = true;
+            let _ = event_tx.send(WsEvent::BgbDisconnected);
+        }
+
+        for (dir, byte) in stats.take_byte_trace() {
+            let _ = event_tx.send(WsEvent::ByteTrace { dir, byte });
         }
 
         // Forward game events to browser as JSON
-        while let Ok(event) = game_event_rx.try_recv() {
-            match &event {
-                GameEvent::Log(msg) => {
-                    let _ = event_tx.send(WsEvent::Log(msg.clone()));
+        let mut idle = false;
+        if let Session::Json(game) = &session {
+            let mut events: Vec<GameEvent> = game.event_rx.try_iter().collect();
+            if congested || events.len() > EVENT_BACKLOG_LIMIT {
+                let dropped = shed_events(&mut events);
+                if dropped > 0 {
+                    let _ = event_tx.send(WsEvent::Log(format!(
+                        "Browser is slow, dropped {} height/heartbeat events", dropped)));
                 }
-                _ => {
-                    let json = game_event_to_json(&event);
-                    if let Err(e) = websocket.write(Message::Text(json)) {
+            }
+            for event in events {
+                idle |= matches!(event, GameEvent::IdleTimeout);
+                bgb_lost |= report_game_event(&event, metrics, event_tx);
+                match &event {
+                    GameEvent::Log(_) => continue,
+                    GameEvent::BgbConnected { version, stats: new_stats } => {
+                        // Follow the new link from here on
+                        retired_exchanges += stats.exchanges.load(Ordering::Relaxed);
+                        stats = new_stats.clone();
+                        metrics.attach_link(stats.clone());
+                        bgb_lost = false;
+                        if let Some(version) = version {
+                            let _ = event_tx.send(WsEvent::BgbConnected(*version));
+                            let _ = event_tx.send(WsEvent::Log("Reconnected to BGB".into()));
+                        }
+                    }
+                    // The GUI forgot the match when the browser dropped
+                    GameEvent::Status { phase, .. } if resuming => {
+                        resuming = false;
+                        if *phase == "in_game" {
+                            let _ = event_tx.send(WsEvent::MatchStarted);
+                        }
+                    }
+                    _ => {}
+                }
+
+                let json = match event {
+                    GameEvent::Config(_) => config_json(&event, config),
+                    _ => game_event_to_json(&event),
+                };
+                // The browser isn't draining its socket: shed load until
+                // a flush goes through, holding back what it must still get
+                let keep = !matches!(event, GameEvent::Height(_)) && !is_sheddable(&event);
+                match write_event(&mut websocket, &mut held, Message::Text(json), keep) {
+                    Ok(backed_up) => congested |= backed_up,
+                    Err(e) => {
                         let _ = event_tx.send(WsEvent::Log(format!("WebSocket write error: {}", e)));
-                        let _ = game_cmd_tx.send(GameCommand::Stop);
+                        let _ = game.cmd_tx.send(GameCommand::Stop);
                         break;
                     }
-                    let _ = websocket.flush();
                 }
             }
         }
+        if congested && drain_held(&mut websocket, &mut held) {
+            congested = false;
+            let _ = event_tx.send(WsEvent::Log("Browser caught up with events".into()));
+        }
+
+        if idle {
+            let _ = websocket.close(None);
+            let _ = websocket.flush();
+            reason = StopReason::Idle;
+            break;
+        }
 
         // Read WebSocket messages from browser
         let msg = match websocket.read() {
             Ok(msg) => msg,
-            Err(tungstenite::Error::Io(ref e))
-                if e.kind() == std::io::ErrorKind::WouldBlock
-                    || e.kind() == std::io::ErrorKind::TimedOut =>
-            {
+            Err(tungstenite::Error::Io(ref e)) if is_timeout(e) => {
                 continue;
             }
+            Err(tungstenite::Error::Capacity(e)) => {
+                let _ = event_tx.send(WsEvent::Log(format!("Rejected oversized browser message: {}", e)));
+                let _ = websocket.close(Some(CloseFrame {
+                    code: CloseCode::Size,
+                    reason: "message too large".into(),
+                }));
+                let _ = websocket.flush();
+                break;
+            }
             Err(e) => {
                 let _ = event_tx.send(WsEvent::Log(format!("WebSocket read error: {}", e)));
                 break;
@@ -184,82 +946,1200 @@ fn handle_connection(
 
         match msg {
             Message::Text(text) => {
-                if let Some(cmd) = parse_browser_command(&text) {
-                    if game_cmd_tx.send(cmd).is_err() {
-                        let _ = event_tx.send(WsEvent::Log("Game thread died".into()));
-                        break;
+                if let Session::Pending(_) = session {
+                    let _ = event_tx.send(WsEvent::Log("Browser using JSON mode".into()));
+                    session = session.into_json(config, event_tx);
+                }
+                let Session::Json(game) = &session else {
+                    let _ = event_tx.send(WsEvent::Log("Ignoring text message on binary connection".into()));
+                    continue;
+                };
+
+                // Each command in a batch is acked or nacked on its own
+                let mut closed = false;
+                for text in split_batch(&text) {
+                    let name = command_name(&text);
+                    if name == "identify" {
+                        let reply = match parse_identify(&text) {
+                            Ok(client) => {
+                                let _ = event_tx.send(WsEvent::Log(format!("Browser identified as {}", client)));
+                                let _ = event_tx.send(WsEvent::BrowserIdentified(client.clone()));
+                                let mut ack = serde_json::json!({ "event": "ack", "cmd": name, "name": client.name });
+                                if let Some(version) = client.version {
+                                    ack["version"] = version.into();
+                                }
+                                versioned(ack)
+                            }
+                            Err(reason) => nack_json(&name, &reason),
+                        };
+                        match write_event(&mut websocket, &mut held, Message::Text(reply), true) {
+                            Ok(backed_up) => congested |= backed_up,
+                            Err(e) => {
+                                let _ = event_tx.send(WsEvent::Log(format!("WebSocket write error: {}", e)));
+                                closed = true;
+                                break;
+                            }
+                        }
+                        continue;
+                    }
+                    let reply = match parse_browser_command(&text) {
+                        Ok(cmd) => {
+                            if game.cmd_tx.send(cmd).is_err() {
+                                let _ = event_tx.send(WsEvent::Log("Game thread died".into()));
+                                closed = true;
+                                break;
+                            }
+                            ack_json(&name)
+                        }
+                        Err(reason) => {
+                            let _ = event_tx.send(WsEvent::Log(format!("Rejected command ({}): {}", reason, text)));
+                            nack_json(&name, &reason)
+                        }
+                    };
+                    // Replies queue behind held events like any frame the
+                    // browser must get
+                    match write_event(&mut websocket, &mut held, Message::Text(reply), true) {
+                        Ok(backed_up) => congested |= backed_up,
+                        Err(e) => {
+                            let _ = event_tx.send(WsEvent::Log(format!("WebSocket write error: {}", e)));
+                            closed = true;
+                            break;
+                        }
+                    }
+                }
+                if closed {
+                    break;
+                }
+            }
+            Message::Binary(data) => {
+                if let Session::Pending(_) = session {
+                    let _ = event_tx.send(WsEvent::Log("Browser using binary mode".into()));
+                    session = session.into_binary();
+                }
+                let Session::Binary(bridge) = &session else {
+                    let _ = event_tx.send(WsEvent::Log("Ignoring binary message on JSON connection".into()));
+                    continue;
+                };
+
+                match bridge.handle_message(&data) {
+                    Ok(response) => {
+                        if let Err(e) = websocket.write(Message::Binary(response)) {
+                            let _ = event_tx.send(WsEvent::Log(format!("WebSocket write error: {}", e)));
+                            break;
+                        }
+                        let _ = websocket.flush();
+                    }
+                    Err(e) => {
+                        let _ = event_tx.send(WsEvent::Log(format!("Bridge exchange error: {}", e)));
                     }
-                } else {
-                    let _ = event_tx.send(WsEvent::Log(format!("Unknown command: {}", text)));
                 }
             }
             Message::Close(_) => {
-                let _ = event_tx.send(WsEvent::Log("Browser disconnected".into()));
+                // Stopping the session below stops the game thread, and the
+                // BGB thread then sends BGB a disconnect
+                let _ = event_tx.send(WsEvent::Log("Browser closed the connection".into()));
+                reason = StopReason::BrowserClosed;
                 break;
             }
             _ => {
-                // Ignore binary, ping, pong
+                // Ignore ping, pong
             }
         }
     }
 
-    // Clean up game thread
-    let _ = game_cmd_tx.send(GameCommand::Stop);
-    let _ = game_thread.join();
+    // Keep a JSON game running for a browser that may come back
+    let session = match (session, config.reconnect_grace, token) {
+        (Session::Json(game), Some(grace), Some(token)) if reason == StopReason::BrowserLost && !bgb_lost => {
+            let _ = event_tx.send(WsEvent::Log(format!(
+                "Keeping the game for {}s in case the browser reconnects", grace.as_secs())));
+            metrics.detach_link();
+            let parked = Parked { token, game, stats, expires: Instant::now() + grace };
+            return (reason, 0, Some(parked));
+        }
+        (session, _, _) => session,
+    };
 
+    // Clean up the game thread and the BGB connection
+    if !session.shutdown() {
+        let _ = event_tx.send(WsEvent::Log("BGB thread did not exit cleanly".into()));
+    }
+
+    // Only reaches the GUI if nothing has reported the link down already
     let _ = event_tx.send(WsEvent::BgbDisconnected);
+    if bgb_lost && reason == StopReason::BrowserLost {
+        // The browser left after BGB had already gone; BGB is the root cause
+        reason = StopReason::BgbLost;
+    }
+
+    metrics.detach_link();
+    let exchanges = retired_exchanges + stats.exchanges.load(Ordering::Relaxed);
+    let _ = event_tx.send(WsEvent::Log(format!("Session ended: {} ({} exchanges)", reason, exchanges)));
+    (reason, exchanges, None)
+}
+
+/// Connect to BGB (or set up a lazy or simulated link) for a new browser
+/// session, reporting the outcome to the GUI. None if BGB could not be reached.
+fn open_session(config: &ServerConfig, event_tx: &EventSender) -> Option<(Session, Arc<LinkStats>)> {
+    if config.lazy_bgb && !config.simulate {
+        let _ = event_tx.send(WsEvent::Log("Lazy BGB: connecting once a game starts".into()));
+    }
+    let bgb = match session_link(config, event_tx) {
+        Ok((b, version)) => {
+            match version {
+                Some(version) => {
+                    let _ = event_tx.send(WsEvent::BgbConnected(version));
+                    let _ = event_tx.send(WsEvent::Log("Connected to BGB".into()));
+                }
+                None if config.simulate => {
+                    let _ = event_tx.send(WsEvent::Log("Simulation mode: not connecting to BGB".into()));
+                }
+                None => {}
+            }
+            b
+        }
+        Err(e) => {
+            let _ = event_tx.send(WsEvent::Log(format!("BGB connect failed: {}", e)));
+            let _ = event_tx.send(WsEvent::BgbDisconnected);
+            return None;
+        }
+    };
+    let stats = bgb.stats();
+
+    // The first data message decides whether this is a JSON or binary client
+    Some((Session::Pending(bgb), stats))
+}
+
+/// The link a browser session plays over: BGB, connected now or (in lazy
+/// mode) once a game needs it, or a simulated link. BGB thread logs are
+/// forwarded to the GUI.
+fn session_link(
+    config: &ServerConfig,
+    event_tx: &EventSender,
+) -> Result<(Box<dyn Link>, Option<BgbVersion>), String> {
+    let bgb_log_tx = log_forwarder(event_tx);
+    if config.lazy_bgb && !config.simulate {
+        Ok((lazy_link(config, bgb_log_tx, event_tx), None))
+    } else {
+        open_link(config, Some(bgb_log_tx))
+    }
+}
+
+/// Pass on what the GUI and metrics track from a game event. Returns true
+/// when the event says the BGB link dropped.
+fn report_game_event(event: &GameEvent, metrics: &Metrics, event_tx: &EventSender) -> bool {
+    match event {
+        GameEvent::Log(msg) => {
+            let _ = event_tx.send(WsEvent::Log(msg.clone()));
+        }
+        GameEvent::GameStarted => {
+            let _ = event_tx.send(WsEvent::MatchStarted);
+        }
+        GameEvent::Win => {
+            metrics.wins.fetch_add(1, Ordering::Relaxed);
+            let _ = event_tx.send(WsEvent::MatchEnded { won: true });
+        }
+        GameEvent::Lose => {
+            metrics.losses.fetch_add(1, Ordering::Relaxed);
+            let _ = event_tx.send(WsEvent::MatchEnded { won: false });
+        }
+        GameEvent::MatchEnded | GameEvent::SessionTimeout { .. } => {
+            let _ = event_tx.send(WsEvent::MatchAborted);
+        }
+        GameEvent::BgbDisconnected => {
+            let _ = event_tx.send(WsEvent::BgbDisconnected);
+            return true;
+        }
+        _ => {}
+    }
+    false
+}
+
+/// A JSON game whose browser dropped, kept running until `expires` so a
+/// browser presenting `token` can pick it up again.
+struct Parked {
+    token: String,
+    game: GameSession,
+    stats: Arc<LinkStats>,
+    expires: Instant,
+}
+
+impl Parked {
+    /// Drain the game's events so they don't pile up with no browser to
+    /// read them. The GUI and metrics still hear about them; what only the
+    /// browser would see is dropped, since a resuming browser gets a fresh
+    /// status.
+    fn forward_events(&self, metrics: &Metrics, event_tx: &EventSender) {
+        for event in self.game.event_rx.try_iter() {
+            report_game_event(&event, metrics, event_tx);
+        }
+    }
+
+    /// Stop the game and release BGB, as a session ending normally would.
+    /// Returns the exchanges made over the whole game.
+    fn end(self, event_tx: &EventSender) -> u64 {
+        if !Session::Json(self.game).shutdown() {
+            let _ = event_tx.send(WsEvent::Log("BGB thread did not exit cleanly".into()));
+        }
+        let _ = event_tx.send(WsEvent::BgbDisconnected);
+        let exchanges = self.stats.exchanges.load(Ordering::Relaxed);
+        let _ = event_tx.send(WsEvent::Log(format!("Session ended: {} ({} exchanges)",
+            StopReason::BrowserLost, exchanges)));
+        exchanges
+    }
+}
+
+/// Token a browser presents to resume its game. It only has to tell
+/// sessions apart, so std's randomly keyed hasher is enough.
+fn new_token() -> String {
+    use std::hash::{BuildHasher, Hasher};
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u128(std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default());
+    format!("{:016x}", hasher.finish())
+}
+
+/// The `session` parameter of a WebSocket URL query, e.g. `session=abc`.
+fn session_token(query: Option<&str>) -> Option<String> {
+    query?.split('&')
+        .find_map(|pair| pair.strip_prefix("session="))
+        .filter(|token| !token.is_empty())
+        .map(str::to_string)
+}
+
+/// The WebSocket subprotocols a client can request to fix its mode up
+/// front. Clients that request none are sorted out by their first data
+/// message instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Subprotocol {
+    Json,
+    Binary,
+}
+
+impl Subprotocol {
+    fn name(self) -> &'static str {
+        match self {
+            Subprotocol::Json => "bgb-json",
+            Subprotocol::Binary => "bgb-binary",
+        }
+    }
+}
+
+/// Pick the first subprotocol we speak from a `Sec-WebSocket-Protocol`
+/// header. Offering only protocols we don't speak is an error, since the
+/// client would otherwise talk past us.
+fn choose_subprotocol(offered: Option<&str>) -> Result<Option<Subprotocol>, String> {
+    let Some(offered) = offered else { return Ok(None) };
+    offered.split(',')
+        .map(str::trim)
+        .find_map(|name| [Subprotocol::Json, Subprotocol::Binary].into_iter().find(|p| p.name() == name))
+        .map(Some)
+        .ok_or_else(|| format!("Unsupported subprotocol {} (expected bgb-json or bgb-binary)", offered))
+}
+
+// ── Connection modes ───────────────────────────────────────────────────
+
+/// How a browser connection's session starts.
+enum Opening {
+    /// Pick up the game parked when this browser dropped
+    Resume(Parked),
+    /// Open a new BGB link, in the mode the browser negotiated if any
+    New(Option<Subprotocol>),
+}
+
+/// What a browser connection is driving, decided by its negotiated
+/// subprotocol or else its first data message.
+enum Session {
+    /// No data message yet; holds the BGB client until the mode is known
+    Pending(Box<dyn Link>),
+    /// JSON commands driving the game thread
+    Json(GameSession),
+    /// Raw firmware bytes exchanged through `Bridge`
+    Binary(Bridge),
+}
+
+/// Handles for a running game thread.
+struct GameSession {
+    cmd_tx: mpsc::Sender<GameCommand>,
+    event_rx: mpsc::Receiver<GameEvent>,
+    /// Returns the link once the game thread stops
+    thread: std::thread::JoinHandle<Box<dyn Link>>,
+}
+
+impl Session {
+    /// Spawn the game thread on a pending session's BGB client.
+    fn into_json(self, config: &ServerConfig, ws_event_tx: &EventSender) -> Self {
+        let Session::Pending(bgb) = self else { return self };
+        // reconnect_bgb opens links the same way this session's was opened
+        let reconnect: LinkFactory = {
+            let config = config.clone();
+            let ws_event_tx = ws_event_tx.clone();
+            Box::new(move || session_link(&config, &ws_event_tx))
+        };
+        let (cmd_tx, cmd_rx) = mpsc::channel::<GameCommand>();
+        let (event_tx, event_rx) = mpsc::channel::<GameEvent>();
+        let game_config = config.game.clone();
+        let speed = config.speed.clone();
+        let verbose = config.verbose.clone();
+        let thread = std::thread::spawn(move || {
+            let mut game = GameThread::new(bgb, cmd_rx, event_tx, game_config, speed, verbose, Box::new(SystemClock));
+            game.set_reconnect(reconnect);
+            game.run();
+            game.into_link()
+        });
+        Session::Json(GameSession { cmd_tx, event_rx, thread })
+    }
+
+    /// Hand a pending session's BGB client to a raw byte bridge.
+    fn into_binary(self) -> Self {
+        let Session::Pending(bgb) = self else { return self };
+        Session::Binary(Bridge::new(bgb))
+    }
+
+    /// Stop the game thread (if any) and shut down the BGB client. Returns
+    /// false if the client's thread did not exit cleanly.
+    fn shutdown(self) -> bool {
+        let link = match self {
+            Session::Pending(bgb) => bgb,
+            Session::Json(game) => {
+                let _ = game.cmd_tx.send(GameCommand::Stop);
+                match game.thread.join() {
+                    Ok(bgb) => bgb,
+                    Err(_) => return false,
+                }
+            }
+            Session::Binary(bridge) => bridge.into_link(),
+        };
+        link.shutdown()
+    }
 }
 
 // ── JSON message handling ──────────────────────────────────────────────
 
+fn send_text(
+    websocket: &mut tungstenite::WebSocket<std::net::TcpStream>,
+    json: String,
+) -> Result<(), String> {
+    websocket.write(Message::Text(json)).map_err(|e| e.to_string())?;
+    websocket.flush().map_err(|e| e.to_string())
+}
+
+/// Split a text message into the commands it carries. tungstenite already
+/// reassembles fragmented frames, so a message is always complete; a JSON
+/// array is a batch of commands to run in order, anything else is a single
+/// command (and is left for `parse_browser_command` to reject if invalid).
+fn split_batch(text: &str) -> Vec<String> {
+    match serde_json::from_str::<serde_json::Value>(text) {
+        Ok(serde_json::Value::Array(items)) => items.iter().map(|item| item.to_string()).collect(),
+        _ => vec![text.to_string()],
+    }
+}
+
+/// The `cmd` field of a browser message, or "" if it has none.
+fn command_name(text: &str) -> String {
+    serde_json::from_str::<serde_json::Value>(text)
+        .ok()
+        .and_then(|json| json.get("cmd")?.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+fn ack_json(cmd: &str) -> String {
+    versioned(serde_json::json!({ "event": "ack", "cmd": cmd }))
+}
+
+fn nack_json(cmd: &str, reason: &str) -> String {
+    versioned(serde_json::json!({ "event": "nack", "cmd": cmd, "reason": reason }))
+}
+
+/// Serialize a browser-bound message, stamped with the schema version.
+fn versioned(mut value: serde_json::Value) -> String {
+    value["v"] = PROTOCOL_VERSION.into();
+    value.to_string()
+}
+
+/// First message on every connection, for feature detection by the browser.
+/// Carries the session token when the game can be resumed by reconnecting
+/// to `ws://…/?session=<token>`.
+fn hello_json(token: Option<&str>) -> String {
+    let capabilities: Vec<String> = COMMANDS.iter().chain(CONNECTION_COMMANDS).map(|c| format!("cmd:{}", c))
+        .chain(EVENTS.iter().map(|e| format!("event:{}", e)))
+        .collect();
+    let mut hello = serde_json::json!({ "event": "hello", "capabilities": capabilities });
+    if let Some(token) = token {
+        hello["session"] = token.into();
+    }
+    versioned(hello)
+}
+
 fn game_event_to_json(event: &GameEvent) -> String {
+    versioned(game_event_value(event))
+}
+
+/// A `GetConfig` reply plus the bridge-wide settings the game thread
+/// doesn't know about.
+fn config_json(event: &GameEvent, config: &ServerConfig) -> String {
+    let mut value = game_event_value(event);
+    value["reconnect_grace_ms"] = config.reconnect_grace.map(|d| d.as_millis() as u64).into();
+    value["lazy_bgb"] = config.lazy_bgb.into();
+    value["simulate"] = config.simulate.into();
+    versioned(value)
+}
+
+/// An event's JSON fields. Keys of the config event match the fields of
+/// the commands that change them, so settings can round-trip.
+fn game_event_value(event: &GameEvent) -> serde_json::Value {
+    use serde_json::json;
+    let ms = |d: Duration| d.as_millis() as u64;
     match event {
-        GameEvent::Connected => r#"{"event":"connected"}"#.to_string(),
-        GameEvent::Height(v) => format!(r#"{{"event":"height","value":{}}}"#, v),
-        GameEvent::Lines(v) => format!(r#"{{"event":"lines","value":{}}}"#, v),
-        GameEvent::Win => r#"{"event":"win"}"#.to_string(),
-        GameEvent::Lose => r#"{"event":"lose"}"#.to_string(),
-        GameEvent::ScreenFilled => r#"{"event":"screen_filled"}"#.to_string(),
+        GameEvent::Connected => json!({ "event": "connected" }),
+        GameEvent::GameStarted => json!({ "event": "game_started" }),
+        GameEvent::RawByte { sent, received } => json!({ "event": "raw_byte", "sent": sent, "received": received }),
+        GameEvent::LoopOverride(v) => json!({ "event": "loop_override", "value": v }),
+        GameEvent::MusicSet(v) => json!({ "event": "music_set", "value": v }),
+        GameEvent::Height(v) => json!({ "event": "height", "value": v }),
+        GameEvent::Lines { value, count } => json!({ "event": "lines", "value": value, "count": count }),
+        GameEvent::Win => json!({ "event": "win" }),
+        GameEvent::Lose => json!({ "event": "lose" }),
+        GameEvent::ScreenFilled => json!({ "event": "screen_filled" }),
+        GameEvent::Error(msg) => json!({ "event": "error", "message": msg }),
+        GameEvent::Warning(msg) => json!({ "event": "warning", "message": msg }),
+        GameEvent::IdleTimeout => json!({ "event": "idle_timeout" }),
+        GameEvent::SessionTimeout { limit } => json!({ "event": "session_timeout", "limit_ms": ms(*limit) }),
+        GameEvent::Desync { count } => json!({ "event": "desync", "count": count }),
+        GameEvent::Timing { target_ms, actual_ms, jitter_ms } => json!({
+            "event": "timing",
+            "target_ms": target_ms,
+            "actual_ms": actual_ms,
+            "jitter_ms": jitter_ms,
+        }),
+        GameEvent::SlowLink { latency_ms, tick_ms } => json!({
+            "event": "slow_link",
+            "latency_ms": latency_ms,
+            "tick_ms": tick_ms,
+            "message": "BGB is answering slower than the game ticks; increase its emulation speed",
+        }),
+        GameEvent::Unhandled(v) => json!({ "event": "unhandled", "value": v }),
+        GameEvent::HeightEcho(v) => json!({ "event": "height_echo", "value": v }),
+        GameEvent::Config(snapshot) => json!({
+            "event": "config",
+            "tick_ms": ms(snapshot.tick),
+            "speed": snapshot.speed,
+            "heartbeat_interval_ms": snapshot.config.heartbeat_interval.map(ms),
+            "desync_threshold": snapshot.config.desync_threshold,
+            "queue_limit": snapshot.config.queue_limit,
+            "idle_timeout_ms": snapshot.config.idle_timeout.map(ms),
+            "max_session_ms": snapshot.config.max_session.map(ms),
+            "probe_diagnostic_after": snapshot.config.probe_diagnostic_after,
+            "auto_final_screen": snapshot.config.auto_final_screen,
+            "echo_raw_bytes": snapshot.config.echo_raw_bytes,
+            "report_unhandled": snapshot.config.report_unhandled,
+            "probe_retry_ms": ms(snapshot.config.probe_retry),
+            "probe_error_retry_ms": ms(snapshot.config.probe_error_retry),
+            "garbage_delay_ms": snapshot.config.start_timing.garbage_delay_ms,
+            "tile_delay_ms": snapshot.config.start_timing.tile_delay_ms,
+            "start_burst": snapshot.config.start_timing.burst,
+            "follow_bgb_speed": snapshot.config.follow_bgb_speed,
+            "queue_policy": snapshot.queue_policy.name(),
+            "loop_override": snapshot.loop_override,
+            "idle_reply": snapshot.idle_reply,
+            "log_mask": snapshot.log_mask,
+        }),
+        GameEvent::Snapshot(state) => json!({ "event": "snapshot", "state": state }),
+        GameEvent::Status { phase, game, music, height, game_ended } => json!({
+            "event": "status",
+            "phase": phase,
+            "game": game,
+            "music": music,
+            "height": height,
+            "game_ended": game_ended,
+        }),
+        GameEvent::CommandSent(v) => json!({ "event": "command_sent", "value": v }),
+        GameEvent::EmuPaused(paused) => json!({ "event": "emu_paused", "paused": paused }),
+        GameEvent::Packets(records) => json!({
+            "event": "packets",
+            "packets": records.iter().map(|r| json!({
+                "index": r.index,
+                "direction": match r.direction {
+                    Direction::Sent => "sent",
+                    Direction::Received => "received",
+                },
+                "command": r.packet.command,
+                "data": r.packet.data,
+                "extra1": r.packet.extra1,
+                "extra2": r.packet.extra2,
+                "timestamp": r.packet.timestamp,
+            })).collect::<Vec<_>>(),
+        }),
+        GameEvent::Games(profiles) => json!({
+            "event": "games",
+            "games": profiles.iter().map(|p| json!({
+                "name": p.name,
+                "music": p.music.iter()
+                    .map(|(name, byte)| json!({ "name": name, "byte": byte }))
+                    .collect::<Vec<_>>(),
+            })).collect::<Vec<_>>(),
+        }),
+        GameEvent::MatchEnded => json!({ "event": "match_ended" }),
+        GameEvent::ProbeDiagnostic(d) => json!({
+            "event": "probe_diagnostic",
+            "expected": d.expected,
+            "seen": d.seen.iter()
+                .map(|(byte, count)| json!({ "byte": byte, "count": count }))
+                .collect::<Vec<_>>(),
+            "message": d.to_string(),
+        }),
+        GameEvent::SelfTestResult(r) => json!({
+            "event": "selftest",
+            "ok": r.ok,
+            "response_byte": r.response_byte,
+            "latency_ms": r.latency_ms,
+        }),
+        GameEvent::SequenceResponse(bytes) => json!({ "event": "seq_response", "bytes": bytes }),
+        GameEvent::TimedSequenceProgress { sent, total } => json!({ "event": "seq_progress", "sent": sent, "total": total }),
+        GameEvent::TimedSequenceDone { steps } => json!({ "event": "seq_done", "steps": steps }),
+        GameEvent::Heartbeat { phase, uptime_ms } => json!({
+            "event": "heartbeat",
+            "phase": phase,
+            "uptime_ms": uptime_ms,
+        }),
+        GameEvent::BgbDisconnected => json!({ "event": "bgb_disconnected" }),
+        GameEvent::BgbConnected { version, .. } => json!({
+            "event": "bgb_connected",
+            "version": version.map(|v| v.to_string()),
+        }),
+        GameEvent::LatencyHistogram(buckets) => json!({
+            "event": "latency_histogram",
+            "buckets": buckets,
+            "bounds_ms": LATENCY_BUCKETS_MS,
+        }),
         GameEvent::Log(_) => unreachable!(), // handled separately
     }
 }
 
-fn parse_browser_command(text: &str) -> Option<GameCommand> {
-    let json: serde_json::Value = serde_json::from_str(text).ok()?;
-    let cmd = json.get("cmd")?.as_str()?;
+/// Typed access to a command's fields. Errors name the command and field
+/// so the browser's nack says exactly what was wrong.
+struct Fields<'a> {
+    cmd: &'a str,
+    json: &'a serde_json::Value,
+}
+
+impl<'a> Fields<'a> {
+    fn get(&self, name: &str) -> Result<&'a serde_json::Value, String> {
+        self.json.get(name).ok_or_else(|| format!("cmd {} missing field {}", self.cmd, name))
+    }
+
+    fn invalid(&self, name: &str, expected: &str) -> String {
+        format!("cmd {} invalid field {}: expected {}", self.cmd, name, expected)
+    }
+
+    fn str(&self, name: &str) -> Result<&'a str, String> {
+        self.get(name)?.as_str().ok_or_else(|| self.invalid(name, "a string"))
+    }
+
+    fn u64(&self, name: &str) -> Result<u64, String> {
+        self.get(name)?.as_u64().ok_or_else(|| self.invalid(name, "a non-negative integer"))
+    }
+
+    fn bool(&self, name: &str) -> Result<bool, String> {
+        self.get(name)?.as_bool().ok_or_else(|| self.invalid(name, "a boolean"))
+    }
+
+    fn f64(&self, name: &str) -> Result<f64, String> {
+        self.get(name)?.as_f64().ok_or_else(|| self.invalid(name, "a number"))
+    }
+
+    /// A field that must fit in a byte.
+    fn byte(&self, name: &str) -> Result<u8, String> {
+        u8::try_from(self.u64(name)?).map_err(|_| self.invalid(name, "a byte (0-255)"))
+    }
+
+    fn byte_array(&self, name: &str) -> Result<Vec<u8>, String> {
+        self.get(name)?
+            .as_array()
+            .ok_or_else(|| self.invalid(name, "an array of bytes"))?
+            .iter()
+            .map(|v| {
+                v.as_u64()
+                    .and_then(|n| u8::try_from(n).ok())
+                    .ok_or_else(|| self.invalid(name, "an array of bytes (0-255)"))
+            })
+            .collect()
+    }
+
+    /// Run `parse` on a field only if it is present.
+    fn optional<T>(&self, name: &str, parse: impl Fn(&str) -> Result<T, String>) -> Result<Option<T>, String> {
+        match self.json.get(name) {
+            Some(_) => parse(name).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Parse `{"cmd":"identify","name":...,"version":...}`; version is optional.
+fn parse_identify(text: &str) -> Result<ClientInfo, String> {
+    let json: serde_json::Value = serde_json::from_str(text).map_err(|e| format!("invalid JSON: {}", e))?;
+    let fields = Fields { cmd: "identify", json: &json };
+    let name = fields.str("name")?.trim();
+    if name.is_empty() {
+        return Err(fields.invalid("name", "a non-empty string"));
+    }
+    let version = fields.optional("version", |f| fields.str(f).map(str::to_string))?;
+    Ok(ClientInfo { name: name.to_string(), version })
+}
+
+fn parse_browser_command(text: &str) -> Result<GameCommand, String> {
+    let json: serde_json::Value = serde_json::from_str(text).map_err(|e| format!("invalid JSON: {}", e))?;
+    let cmd = json.get("cmd")
+        .ok_or("missing field cmd")?
+        .as_str()
+        .ok_or("invalid field cmd: expected a string")?;
+    let fields = Fields { cmd, json: &json };
 
     match cmd {
         "set_game" => {
-            let game = json.get("game")?.as_str()?.to_string();
-            Some(GameCommand::SetGame(game))
+            let game = fields.str("game")?.to_string();
+            // The game's profile supplies the probe; optional overrides are
+            // for cartridges that answer it differently
+            let mut probe = game::profile(&game).map_or_else(Probe::default, |p| p.probe);
+            if let Some(send) = fields.optional("probe_send", |f| fields.byte(f))? {
+                probe.send = send;
+            }
+            if let Some(expect) = fields.optional("probe_expect", |f| fields.byte(f))? {
+                probe.expect = expect;
+            }
+            Ok(GameCommand::SetGame { game, probe })
         }
         "set_music" => {
-            let music = json.get("music")?.as_u64()? as u8;
-            Some(GameCommand::SetMusic(music))
+            let music = fields.byte("music")?;
+            Ok(GameCommand::SetMusic(music))
+        }
+        "confirm_music" => Ok(GameCommand::ConfirmMusic),
+        "end_match" => Ok(GameCommand::EndMatch),
+        "selftest" => Ok(GameCommand::SelfTest),
+        "list_games" => Ok(GameCommand::ListGames),
+        "reconnect_bgb" => Ok(GameCommand::ReconnectBgb),
+        "snapshot_state" => Ok(GameCommand::SnapshotState),
+        "force_in_game" => Ok(GameCommand::ForceInGame),
+        "restore_state" => Ok(GameCommand::RestoreState(fields.str("state")?.to_string())),
+        "query_status" => Ok(GameCommand::QueryStatus),
+        "get_config" => Ok(GameCommand::GetConfig),
+        "set_log_mask" => {
+            let mask = fields.byte("value")?;
+            Ok(GameCommand::SetLogMask(mask))
+        }
+        "get_packets" => Ok(GameCommand::GetPackets),
+        "latency_histogram" => Ok(GameCommand::LatencyHistogram),
+        "get_height" => Ok(GameCommand::GetHeight),
+        "reset_link" => Ok(GameCommand::ResetLink),
+        "final_screen" => Ok(GameCommand::SendFinalScreen),
+        "emu_pause" => {
+            let paused = fields.bool("paused")?;
+            Ok(GameCommand::SetEmuPaused(paused))
+        }
+        "set_clock" => {
+            let clock = SerialClock::from_name(fields.str("mode")?)
+                .ok_or_else(|| fields.invalid("mode", "master or slave"))?;
+            Ok(GameCommand::SetClock(clock))
         }
-        "confirm_music" => Some(GameCommand::ConfirmMusic),
         "start_game" => {
-            let garbage = json.get("garbage")?
-                .as_array()?
-                .iter()
-                .filter_map(|v| v.as_u64().map(|n| n as u8))
-                .collect();
-            let tiles = json.get("tiles")?
-                .as_array()?
+            let garbage = fields.byte_array("garbage")?;
+            let tiles = fields.byte_array("tiles")?;
+            let is_first = fields.optional("is_first", |f| fields.bool(f))?.unwrap_or(true);
+            Ok(GameCommand::StartGame { garbage, tiles, is_first })
+        }
+        "send_seq" => {
+            let bytes = fields.byte_array("bytes")?;
+            Ok(GameCommand::SendSequence(bytes))
+        }
+        "timed_seq" => {
+            let steps = fields.get("steps")?
+                .as_array()
+                .ok_or_else(|| fields.invalid("steps", "an array of steps"))?
                 .iter()
-                .filter_map(|v| v.as_u64().map(|n| n as u8))
-                .collect();
-            let is_first = json.get("is_first").and_then(|v| v.as_bool()).unwrap_or(true);
-            Some(GameCommand::StartGame { garbage, tiles, is_first })
+                .map(|step| {
+                    let step = Fields { cmd, json: step };
+                    Ok((step.byte("byte")?, step.u64("delay_ms")?))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            Ok(GameCommand::TimedSequence(steps))
         }
         "set_height" => {
-            let value = json.get("value")?.as_u64()? as u8;
-            Some(GameCommand::SetHeight(value))
+            let value = fields.byte("value")?;
+            Ok(GameCommand::SetHeight(value))
         }
         "queue_command" => {
-            let value = json.get("value")?.as_u64()? as u8;
-            Some(GameCommand::QueueCommand(value))
+            let value = fields.byte("value")?;
+            Ok(GameCommand::QueueCommand(value))
         }
-        _ => None,
+        "queue_lines" => {
+            let lines = fields.byte("lines")?;
+            Ok(GameCommand::QueueLines(lines))
+        }
+        "set_line_map" => {
+            let entries = fields.get("map")?
+                .as_array()
+                .ok_or_else(|| fields.invalid("map", "an array of {lines, byte} entries"))?
+                .iter()
+                .map(|entry| {
+                    let entry = Fields { cmd, json: entry };
+                    Ok((entry.byte("lines")?, entry.byte("byte")?))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            let map = LineMap::new(entries).map_err(|e| fields.invalid("map", &e))?;
+            Ok(GameCommand::SetLineMap(map))
+        }
+        "loop_override" => {
+            // null clears the override
+            let value = if fields.get("value")?.is_null() {
+                None
+            } else {
+                Some(fields.byte("value")?)
+            };
+            Ok(GameCommand::SetLoopOverride(value))
+        }
+        "set_queue_policy" => {
+            let policy = QueuePolicy::from_name(fields.str("policy")?)
+                .ok_or_else(|| fields.invalid("policy", "strict, interleaved or height_first"))?;
+            Ok(GameCommand::SetQueuePolicy(policy))
+        }
+        "set_speed" => {
+            let value = fields.f64("value")? as f32;
+            Ok(GameCommand::SetSpeed(value))
+        }
+        "set_config" => {
+            let desync_threshold = fields.optional("desync_threshold", |f| {
+                u32::try_from(fields.u64(f)?).map_err(|_| fields.invalid(f, "a 32-bit integer"))
+            })?;
+            let queue_limit = fields.optional("queue_limit", |f| {
+                usize::try_from(fields.u64(f)?).map_err(|_| fields.invalid(f, "a queue length"))
+            })?;
+            // 0 or null disables the idle timeout and match limit
+            let limit = |f: &str| {
+                if fields.get(f)?.is_null() {
+                    return Ok(None);
+                }
+                Ok(match fields.u64(f)? {
+                    0 => None,
+                    ms => Some(std::time::Duration::from_millis(ms)),
+                })
+            };
+            let idle_timeout = fields.optional("idle_timeout_ms", limit)?;
+            let max_session = fields.optional("max_session_ms", limit)?;
+            let probe_diagnostic_after = fields.optional("probe_diagnostic_after", |f| {
+                u32::try_from(fields.u64(f)?).map_err(|_| fields.invalid(f, "a 32-bit integer"))
+            })?;
+            let auto_final_screen = fields.optional("auto_final_screen", |f| fields.bool(f))?;
+            let echo_raw_bytes = fields.optional("echo_raw_bytes", |f| fields.bool(f))?;
+            let report_unhandled = fields.optional("report_unhandled", |f| fields.bool(f))?;
+            let millis = |f: &str| fields.u64(f).map(std::time::Duration::from_millis);
+            let probe_retry = fields.optional("probe_retry_ms", millis)?;
+            let probe_error_retry = fields.optional("probe_error_retry_ms", millis)?;
+            let garbage_delay_ms = fields.optional("garbage_delay_ms", |f| fields.u64(f))?;
+            let tile_delay_ms = fields.optional("tile_delay_ms", |f| fields.u64(f))?;
+            let start_burst = fields.optional("start_burst", |f| match fields.u64(f)? {
+                n @ 1..=64 => Ok(n as usize),
+                _ => Err(fields.invalid(f, "a burst of 1 to 64 bytes")),
+            })?;
+            let follow_bgb_speed = fields.optional("follow_bgb_speed", |f| fields.bool(f))?;
+            Ok(GameCommand::SetConfig(ConfigUpdate {
+                desync_threshold,
+                queue_limit,
+                idle_timeout,
+                max_session,
+                probe_diagnostic_after,
+                auto_final_screen,
+                echo_raw_bytes,
+                report_unhandled,
+                probe_retry,
+                probe_error_retry,
+                garbage_delay_ms,
+                tile_delay_ms,
+                start_burst,
+                follow_bgb_speed,
+            }))
+        }
+        "set_heartbeat" => {
+            // 0 or null disables heartbeats
+            let interval = if fields.get("interval_ms")?.is_null() {
+                None
+            } else {
+                match fields.u64("interval_ms")? {
+                    0 => None,
+                    ms => Some(std::time::Duration::from_millis(ms)),
+                }
+            };
+            Ok(GameCommand::SetHeartbeat(interval))
+        }
+        _ => Err(format!("unknown cmd {}", cmd)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_bgb::FakeBgb;
+    use crate::game::ConfigSnapshot;
+
+    /// In-memory browser socket that refuses writes while `blocked`.
+    #[derive(Clone, Default)]
+    struct MockSocket {
+        blocked: std::rc::Rc<std::cell::Cell<bool>>,
+        written: std::rc::Rc<std::cell::RefCell<Vec<u8>>>,
+    }
+
+    impl Read for MockSocket {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::WouldBlock.into())
+        }
+    }
+
+    impl Write for MockSocket {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.blocked.get() {
+                return Err(std::io::ErrorKind::WouldBlock.into());
+            }
+            self.written.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn parked_game_events_reach_the_gui() {
+        let (cmd_tx, _cmd_rx) = mpsc::channel();
+        let (game_tx, event_rx) = mpsc::channel();
+        let thread = std::thread::spawn(|| Box::new(crate::link::ClosedLink::default()) as Box<dyn Link>);
+        let parked = Parked {
+            token: new_token(),
+            game: GameSession { cmd_tx, event_rx, thread },
+            stats: Arc::default(),
+            expires: Instant::now(),
+        };
+        game_tx.send(GameEvent::Height(3)).unwrap();
+        game_tx.send(GameEvent::Log("still playing".into())).unwrap();
+        game_tx.send(GameEvent::Win).unwrap();
+        game_tx.send(GameEvent::SessionTimeout { limit: Duration::from_secs(60) }).unwrap();
+
+        let (gui_tx, gui_rx) = mpsc::channel();
+        let metrics = Metrics::default();
+        parked.forward_events(&metrics, &EventSender::new(gui_tx));
+        assert!(matches!(&gui_rx.try_iter().collect::<Vec<_>>()[..],
+            [WsEvent::Log(msg), WsEvent::MatchEnded { won: true }, WsEvent::MatchAborted] if msg == "still playing"));
+        assert_eq!(metrics.wins.load(Ordering::Relaxed), 1);
+        assert!(parked.game.event_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn shedding_keeps_results_and_the_newest_height() {
+        let mut events = vec![
+            GameEvent::Height(1),
+            GameEvent::Heartbeat { phase: "in_game", uptime_ms: 0 },
+            GameEvent::Win,
+            GameEvent::Height(2),
+            GameEvent::Error("oops".into()),
+            GameEvent::Unhandled(0x42),
+        ];
+        assert_eq!(shed_events(&mut events), 3);
+        assert!(matches!(&events[..], [GameEvent::Win, GameEvent::Height(2), GameEvent::Error(_)]));
+    }
+
+    #[test]
+    fn congested_browser_still_gets_kept_frames_in_order() {
+        let socket = MockSocket::default();
+        let config = WebSocketConfig { write_buffer_size: 0, max_write_buffer_size: 64, ..Default::default() };
+        let mut websocket = WebSocket::from_raw_socket(socket.clone(), tungstenite::protocol::Role::Server, Some(config));
+        let mut held = VecDeque::new();
+        let frame = |name: &str| Message::Text(format!("{:-<40}", name));
+
+        socket.blocked.set(true);
+        // Buffered, then refused once the buffer is full
+        assert!(write_event(&mut websocket, &mut held, frame("first"), true).unwrap());
+        assert!(write_event(&mut websocket, &mut held, frame("heartbeat"), false).unwrap());
+        assert!(write_event(&mut websocket, &mut held, frame("win"), true).unwrap());
+        assert!(write_event(&mut websocket, &mut held, frame("error"), true).unwrap());
+        assert_eq!(held.len(), 2);
+        assert!(!drain_held(&mut websocket, &mut held));
+
+        socket.blocked.set(false);
+        assert!(drain_held(&mut websocket, &mut held));
+        assert!(held.is_empty());
+        let written = String::from_utf8_lossy(&socket.written.borrow()).into_owned();
+        let at = |name: &str| written.find(name);
+        assert!(at("first") < at("win") && at("win") < at("error"), "{}", written);
+        assert_eq!(at("heartbeat"), None);
+    }
+
+    #[test]
+    fn acks_wait_behind_held_frames() {
+        let socket = MockSocket::default();
+        let config = WebSocketConfig { write_buffer_size: 0, max_write_buffer_size: 64, ..Default::default() };
+        let mut websocket = WebSocket::from_raw_socket(socket.clone(), tungstenite::protocol::Role::Server, Some(config));
+        let mut held = VecDeque::new();
+
+        socket.blocked.set(true);
+        assert!(write_event(&mut websocket, &mut held, Message::Text(format!("{:-<40}", "first")), true).unwrap());
+        assert!(write_event(&mut websocket, &mut held, Message::Text(format!("{:-<40}", "win")), true).unwrap());
+        // A command arriving now is acked without closing the session
+        assert!(write_event(&mut websocket, &mut held, Message::Text(ack_json("ping")), true).unwrap());
+        assert_eq!(held.len(), 2);
+
+        socket.blocked.set(false);
+        assert!(drain_held(&mut websocket, &mut held));
+        let written = String::from_utf8_lossy(&socket.written.borrow()).into_owned();
+        let at = |name: &str| written.find(name);
+        assert!(at("win") < at("\"ack\""), "{}", written);
+    }
+
+    fn reject_reason(text: &str) -> String {
+        match parse_browser_command(text) {
+            Ok(cmd) => panic!("{} parsed as {:?}", text, cmd),
+            Err(reason) => reason,
+        }
+    }
+
+    #[test]
+    fn unknown_command_is_named() {
+        assert_eq!(reject_reason(r#"{"cmd":"warp"}"#), "unknown cmd warp");
+    }
+
+    #[test]
+    fn missing_cmd_and_bad_json_are_reported() {
+        assert_eq!(reject_reason(r#"{"value":1}"#), "missing field cmd");
+        assert!(reject_reason("{not json").starts_with("invalid JSON"));
+    }
+
+    #[test]
+    fn missing_field_is_named() {
+        assert_eq!(reject_reason(r#"{"cmd":"set_game"}"#), "cmd set_game missing field game");
+        assert_eq!(reject_reason(r#"{"cmd":"start_game","garbage":[]}"#), "cmd start_game missing field tiles");
+    }
+
+    #[test]
+    fn wrong_type_is_named() {
+        assert_eq!(reject_reason(r#"{"cmd":"start_game","garbage":[1,"x"],"tiles":[]}"#),
+            "cmd start_game invalid field garbage: expected an array of bytes (0-255)");
+        assert_eq!(reject_reason(r#"{"cmd":"set_height","value":"3"}"#),
+            "cmd set_height invalid field value: expected a non-negative integer");
+        assert_eq!(reject_reason(r#"{"cmd":"set_speed","value":true}"#),
+            "cmd set_speed invalid field value: expected a number");
+    }
+
+    #[test]
+    fn out_of_range_byte_is_rejected() {
+        assert_eq!(reject_reason(r#"{"cmd":"set_game","game":"tetris","probe_send":300}"#),
+            "cmd set_game invalid field probe_send: expected a byte (0-255)");
+    }
+
+    #[test]
+    fn byte_fields_reject_256_instead_of_wrapping() {
+        for cmd in ["set_height", "queue_command"] {
+            assert_eq!(reject_reason(&format!(r#"{{"cmd":"{}","value":256}}"#, cmd)),
+                format!("cmd {} invalid field value: expected a byte (0-255)", cmd));
+        }
+        assert_eq!(reject_reason(r#"{"cmd":"set_music","music":256}"#),
+            "cmd set_music invalid field music: expected a byte (0-255)");
+    }
+
+    #[test]
+    fn byte_arrays_reject_256_instead_of_wrapping() {
+        assert_eq!(reject_reason(r#"{"cmd":"start_game","garbage":[],"tiles":[1,256]}"#),
+            "cmd start_game invalid field tiles: expected an array of bytes (0-255)");
+        assert_eq!(reject_reason(r#"{"cmd":"send_seq","bytes":[256]}"#),
+            "cmd send_seq invalid field bytes: expected an array of bytes (0-255)");
+        assert!(matches!(parse_browser_command(r#"{"cmd":"send_seq","bytes":[0,255]}"#),
+            Ok(GameCommand::SendSequence(bytes)) if bytes == [0, 255]));
+    }
+
+    #[test]
+    fn timed_seq_parses_steps_in_order() {
+        assert!(matches!(parse_browser_command(
+            r#"{"cmd":"timed_seq","steps":[{"byte":96,"delay_ms":150},{"byte":2,"delay_ms":0}]}"#),
+            Ok(GameCommand::TimedSequence(steps)) if steps == [(0x60, 150), (0x02, 0)]));
+        assert_eq!(reject_reason(r#"{"cmd":"timed_seq","steps":[{"byte":96}]}"#),
+            "cmd timed_seq missing field delay_ms");
+    }
+
+    #[test]
+    fn identify_takes_a_name_and_optional_version() {
+        let client = parse_identify(r#"{"cmd":"identify","name":"tetris-ui","version":"1.2"}"#).unwrap();
+        assert_eq!(client.to_string(), "tetris-ui v1.2");
+        let client = parse_identify(r#"{"cmd":"identify","name":"console"}"#).unwrap();
+        assert_eq!(client.to_string(), "console");
+        assert_eq!(parse_identify(r#"{"cmd":"identify","name":" "}"#).unwrap_err(),
+            "cmd identify invalid field name: expected a non-empty string");
+    }
+
+    #[test]
+    fn set_clock_takes_master_or_slave() {
+        assert!(matches!(parse_browser_command(r#"{"cmd":"set_clock","mode":"slave"}"#),
+            Ok(GameCommand::SetClock(SerialClock::Slave))));
+        assert_eq!(reject_reason(r#"{"cmd":"set_clock","mode":"both"}"#),
+            "cmd set_clock invalid field mode: expected master or slave");
+    }
+
+    #[test]
+    fn batch_is_split_into_commands_in_order() {
+        let batch = split_batch(r#"[{"cmd":"set_music","music":28},{"cmd":"confirm_music"}]"#);
+        assert_eq!(batch.len(), 2);
+        assert!(matches!(parse_browser_command(&batch[0]), Ok(GameCommand::SetMusic(0x1C))));
+        assert!(matches!(parse_browser_command(&batch[1]), Ok(GameCommand::ConfirmMusic)));
+        assert_eq!(split_batch(r#"{"cmd":"get_height"}"#), vec![r#"{"cmd":"get_height"}"#]);
+    }
+
+    #[test]
+    fn advertised_commands_are_recognized() {
+        for cmd in COMMANDS {
+            let result = parse_browser_command(&format!(r#"{{"cmd":"{}"}}"#, cmd));
+            assert_ne!(result.err(), Some(format!("unknown cmd {}", cmd)));
+        }
+    }
+
+    #[test]
+    fn only_plain_gets_get_the_status_page() {
+        assert!(is_plain_get(b"GET / HTTP/1.1\r\nHost: localhost:8767\r\n\r\n"));
+        assert!(!is_plain_get(b"GET / HTTP/1.1\r\nConnection: Upgrade\r\nUpgrade: WebSocket\r\n\r\n"));
+        assert!(!is_plain_get(b"POST / HTTP/1.1\r\n\r\n"));
+        assert!(!is_plain_get(b""));
+        // Cut short before the end of the head
+        assert!(!is_plain_get(b"GET / HTTP/1.1\r\nHost: localhost:8767\r\n"));
+
+        let page = status_page(true, Duration::from_secs(3723), 8767);
+        assert!(page.contains("BGB: connected"));
+        assert!(page.contains("1h 02m 03s"));
+        assert!(page.contains("ws://localhost:8767/"));
+    }
+
+    /// The GUI-visible connection state changes in a stream of events.
+    fn transition(event: &WsEvent) -> Option<&'static str> {
+        Some(match event {
+            WsEvent::BrowserConnected => "browser_connected",
+            WsEvent::BrowserDisconnected => "browser_disconnected",
+            WsEvent::BgbConnected(_) => "bgb_connected",
+            WsEvent::BgbDisconnected => "bgb_disconnected",
+            WsEvent::Stopped { .. } => "stopped",
+            _ => return None,
+        })
+    }
+
+    #[test]
+    fn session_transitions_are_each_reported_once() {
+        let bgb = FakeBgb::start(Vec::new());
+        let ws_port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let config = ServerConfig {
+            ws_port,
+            bgb: bgb.endpoint.clone(),
+            relay: None,
+            metrics_port: None,
+            verbose: Verbosity::default(),
+            speed: Speed::default(),
+            game: GameConfig::default(),
+            simulate: false,
+            lazy_bgb: false,
+            bgb_poll: PollInterval::default(),
+            bgb_status: StatusFlags::default(),
+            bgb_timeouts: Timeouts::default(),
+            reconnect_grace: None,
+        };
+        let (event_tx, event_rx) = mpsc::channel();
+        let (cmd_tx, cmd_rx) = mpsc::channel();
+        let server = std::thread::spawn(move || run(config, event_tx, cmd_rx));
+
+        let (mut browser, _) = (0..100)
+            .find_map(|_| {
+                std::thread::sleep(Duration::from_millis(20));
+                tungstenite::connect(format!("ws://127.0.0.1:{}/", ws_port)).ok()
+            })
+            .expect("server never accepted the browser");
+        browser.send(Message::Text(r#"{"cmd":"set_game","game":"tetris"}"#.into())).unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        browser.close(None).unwrap();
+        while browser.read().is_ok() {}
+
+        let mut seen = Vec::new();
+        while !seen.contains(&"browser_disconnected") {
+            let event = event_rx.recv_timeout(Duration::from_secs(5)).expect("session never ended");
+            seen.extend(transition(&event));
+        }
+        cmd_tx.send(WsCommand::Stop).unwrap();
+        server.join().unwrap();
+        seen.extend(event_rx.try_iter().filter_map(|e| transition(&e)));
+
+        assert_eq!(seen, ["browser_connected", "bgb_connected", "bgb_disconnected", "browser_disconnected", "stopped"]);
+        // BGB was told we left, not just dropped
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while bgb.received(109).is_empty() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(bgb.received(109).len(), 1);
+    }
+
+    #[test]
+    fn first_supported_subprotocol_is_chosen() {
+        assert_eq!(choose_subprotocol(None), Ok(None));
+        assert_eq!(choose_subprotocol(Some("bgb-binary")), Ok(Some(Subprotocol::Binary)));
+        assert_eq!(choose_subprotocol(Some("chat, bgb-json, bgb-binary")), Ok(Some(Subprotocol::Json)));
+        assert!(choose_subprotocol(Some("chat")).is_err());
+    }
+
+    #[test]
+    fn session_token_is_read_from_the_query() {
+        assert_eq!(session_token(Some("session=abc123")), Some("abc123".into()));
+        assert_eq!(session_token(Some("v=1&session=abc123")), Some("abc123".into()));
+        assert_eq!(session_token(Some("session=")), None);
+        assert_eq!(session_token(None), None);
+
+        let hello: serde_json::Value = serde_json::from_str(&hello_json(Some("abc123"))).unwrap();
+        assert_eq!(hello["session"], "abc123");
+        let hello: serde_json::Value = serde_json::from_str(&hello_json(None)).unwrap();
+        assert!(hello.get("session").is_none());
+    }
+
+    #[test]
+    fn config_event_round_trips_through_set_config() {
+        let snapshot = ConfigSnapshot {
+            config: GameConfig::default(),
+            tick: Duration::from_millis(100),
+            speed: 1.0,
+            queue_policy: QueuePolicy::Interleaved,
+            loop_override: None,
+            idle_reply: Some(0x02),
+            log_mask: 0,
+        };
+        let mut json = game_event_value(&GameEvent::Config(snapshot));
+        assert_eq!(json["queue_policy"], "interleaved");
+        assert_eq!(json["probe_retry_ms"], 500);
+
+        json["cmd"] = "set_config".into();
+        let Ok(GameCommand::SetConfig(update)) = parse_browser_command(&json.to_string()) else {
+            panic!("config event did not parse as set_config");
+        };
+        assert_eq!(update.desync_threshold, Some(GameConfig::default().desync_threshold));
+        assert_eq!(update.probe_retry, Some(Duration::from_millis(500)));
+        assert_eq!(update.start_burst, Some(1));
+    }
+
+    #[test]
+    fn events_carry_protocol_version() {
+        let json: serde_json::Value = serde_json::from_str(&game_event_to_json(&GameEvent::Height(3))).unwrap();
+        assert_eq!(json["v"], 1);
+        assert_eq!(json["value"], 3);
+    }
+
+    #[test]
+    fn games_event_lists_music_options() {
+        let json: serde_json::Value = serde_json::from_str(&game_event_to_json(&GameEvent::Games(game::PROFILES))).unwrap();
+        assert_eq!(json["event"], "games");
+        assert_eq!(json["games"][0]["name"], "tetris");
+        assert_eq!(json["games"][0]["music"][0], serde_json::json!({ "name": "a_type", "byte": 28 }));
+    }
+
+    #[test]
+    fn valid_command_parses() {
+        let cmd = parse_browser_command(r#"{"cmd":"set_game","game":"tetris","probe_expect":85}"#).unwrap();
+        assert!(matches!(cmd, GameCommand::SetGame { probe: Probe { send: 0x29, expect: 0x55 }, .. }));
     }
 }